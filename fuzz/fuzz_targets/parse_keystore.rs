@@ -0,0 +1,11 @@
+#![no_main]
+
+use kayring::Keystore;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  // parsing must never panic, and whatever parses must serialize back to the exact same bytes
+  if let Ok(keystore) = Keystore::from_bytes(data) {
+    assert_eq!(keystore.to_bytes(), data);
+  }
+});
@@ -0,0 +1,194 @@
+//! Public keys & addresses of stored keys on various chains.
+use std::fmt;
+use std::str::FromStr;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bech32::{Bech32, Hrp};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::eth;
+use crate::hd::Curve;
+
+/// Chain to derive an address for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chain {
+  /// EIP-55 checksummed Ethereum address.
+  Eth,
+  /// Bech32 Cosmos SDK account address with the given human readable prefix, e.g. `cosmos` or `terra`.
+  Cosmos(String),
+  /// Base58 encoded Ed25519 public key.
+  Solana,
+  /// Native segwit (P2WPKH) Bitcoin mainnet address.
+  Bitcoin,
+}
+
+/// Public key & address of a key on some chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+  pub address: String,
+  /// Public key in the chain's customary representation.
+  pub public_key: String,
+}
+
+impl Chain {
+  /// Name of the derivation preset for mnemonic keystores, see [`crate::hd::preset`].
+  pub fn preset(&self) -> &'static str {
+    match self {
+      Chain::Eth => "eth",
+      Chain::Cosmos(_) => "cosmos",
+      Chain::Solana => "solana",
+      Chain::Bitcoin => "bitcoin",
+    }
+  }
+
+  pub fn curve(&self) -> Curve {
+    match self {
+      Chain::Solana => Curve::Ed25519,
+      _ => Curve::Secp256k1,
+    }
+  }
+
+  /// Derive the public key & address of the given private key.
+  pub fn account(&self, privkey: &[u8]) -> Result<Account> {
+    match self {
+      Chain::Eth => {
+        let key = eth::signing_key(privkey)?;
+        let pubkey = key.verifying_key().to_encoded_point(false);
+        Ok(Account {
+          address: eth::to_checksum_address(&eth::address(privkey)?),
+          public_key: format!("0x{}", hex::encode(pubkey.as_bytes())),
+        })
+      },
+      Chain::Cosmos(prefix) => {
+        let pubkey = compressed_pubkey(privkey)?;
+        let hrp = Hrp::parse(prefix)
+          .map_err(|err| Error::InvalidValue(format!("Invalid bech32 prefix {}: {}", prefix, err)))?;
+        let address = bech32::encode::<Bech32>(hrp, &hash160(&pubkey))
+          .map_err(|err| Error::Other(format!("Failed to encode address: {}", err)))?;
+        Ok(Account {
+          address,
+          public_key: BASE64_STANDARD.encode(pubkey),
+        })
+      },
+      Chain::Solana => {
+        let key = ed25519_signing_key(privkey)?;
+        let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        Ok(Account {
+          address: pubkey.clone(),
+          public_key: pubkey,
+        })
+      },
+      Chain::Bitcoin => {
+        let pubkey = compressed_pubkey(privkey)?;
+        let address = bech32::segwit::encode_v0(bech32::hrp::BC, &hash160(&pubkey))
+          .map_err(|err| Error::Other(format!("Failed to encode address: {}", err)))?;
+        Ok(Account {
+          address,
+          public_key: hex::encode(pubkey),
+        })
+      },
+    }
+  }
+}
+
+impl FromStr for Chain {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.split_once(':') {
+      Some(("cosmos", prefix)) => Ok(Chain::Cosmos(prefix.to_string())),
+      None if s == "eth" => Ok(Chain::Eth),
+      None if s == "solana" => Ok(Chain::Solana),
+      None if s == "bitcoin" => Ok(Chain::Bitcoin),
+      _ => Err(Error::InvalidValue(format!("Unknown chain {}. Expected eth, cosmos:<prefix>, solana or bitcoin", s))),
+    }
+  }
+}
+
+impl fmt::Display for Chain {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Chain::Cosmos(prefix) => write!(f, "cosmos:{}", prefix),
+      chain => write!(f, "{}", chain.preset()),
+    }
+  }
+}
+
+/// Parse an Ed25519 private key given either as 32 byte seed or as 64 byte keypair as used by the
+/// Solana CLI.
+pub fn ed25519_signing_key(privkey: &[u8]) -> Result<ed25519_dalek::SigningKey> {
+  match privkey.len() {
+    32 => Ok(ed25519_dalek::SigningKey::from_bytes(privkey.try_into().unwrap())),
+    64 => ed25519_dalek::SigningKey::from_keypair_bytes(privkey.try_into().unwrap())
+      .map_err(|_| Error::InvalidValue("Value is not a valid Ed25519 keypair".to_string())),
+    _ => Err(Error::InvalidValue("Value is not a valid Ed25519 private key".to_string())),
+  }
+}
+
+/// SEC1 compressed secp256k1 public key of the given private key.
+pub fn compressed_pubkey(privkey: &[u8]) -> Result<[u8; 33]> {
+  let key = eth::signing_key(privkey)?;
+  Ok(key.verifying_key().to_encoded_point(true).as_bytes().try_into().unwrap())
+}
+
+fn hash160(data: &[u8]) -> [u8; 20] {
+  Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hd::{derive, mnemonic_to_seed};
+
+  const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+  fn account(chain: &str, path: &str) -> Account {
+    let chain = chain.parse::<Chain>().unwrap();
+    let seed = mnemonic_to_seed(MNEMONIC, "").unwrap();
+    chain.account(&derive(&seed, path, chain.curve()).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn derives_cosmos_addresses() {
+    let cosmos = account("cosmos:cosmos", "m/44'/118'/0'/0/0");
+    assert_eq!(cosmos.address, "cosmos19rl4cm2hmr8afy4kldpxz3fka4jguq0auqdal4");
+    assert_eq!(BASE64_STANDARD.decode(&cosmos.public_key).unwrap().len(), 33);
+    // the same key under another prefix
+    let terra = account("cosmos:terra", "m/44'/118'/0'/0/0");
+    assert!(terra.address.starts_with("terra1"));
+    assert_eq!(bech32::decode(&terra.address).unwrap().1, bech32::decode(&cosmos.address).unwrap().1);
+  }
+
+  #[test]
+  fn rejects_invalid_bech32_prefixes() {
+    for prefix in ["", "cosmos prefix", "cosmos\u{e9}"] {
+      let chain = Chain::Cosmos(prefix.to_string());
+      assert!(matches!(chain.account(&[1; 32]), Err(Error::InvalidValue(_))), "{:?}", prefix);
+    }
+  }
+
+  #[test]
+  fn derives_solana_addresses() {
+    let account = account("solana", "m/44'/501'/0'/0'");
+    assert_eq!(account.address, "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk");
+    assert_eq!(account.public_key, account.address);
+  }
+
+  #[test]
+  fn accepts_solana_keypairs() {
+    let key = ed25519_signing_key(&[1; 32]).unwrap();
+    assert_eq!(Chain::Solana.account(&key.to_keypair_bytes()).unwrap(), Chain::Solana.account(&[1; 32]).unwrap());
+    assert!(matches!(Chain::Solana.account(&[1; 16]), Err(Error::InvalidValue(_))));
+  }
+
+  #[test]
+  fn parses_chains() {
+    for chain in [Chain::Eth, Chain::Cosmos("terra".to_string()), Chain::Solana, Chain::Bitcoin] {
+      assert_eq!(chain.to_string().parse::<Chain>().unwrap(), chain);
+    }
+    assert!(matches!("cosmos".parse::<Chain>(), Err(Error::InvalidValue(_))));
+    assert!(matches!("eth:mainnet".parse::<Chain>(), Err(Error::InvalidValue(_))));
+  }
+}
@@ -0,0 +1,168 @@
+//! Import & export of the [age](https://age-encryption.org) file format, encrypted with a passphrase
+//! or to X25519 recipients, so keys can be handed to & taken from existing age based tooling.
+use std::io::{Read, Write};
+use std::iter;
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::SecretString;
+use age::{DecryptError, Decryptor, Encryptor};
+
+use crate::error::{Error, Result};
+use crate::keystore::Credential;
+use crate::recipient::Recipient;
+
+/// Encrypt `value` with a passphrase, as done by `age --passphrase`. If `armor`, the file is PEM
+/// encoded as done by `age --armor`.
+pub fn encrypt_with_passphrase(value: &[u8], passphrase: impl AsRef<str>, armor: bool) -> Result<Vec<u8>> {
+  let passphrase = SecretString::from(passphrase.as_ref().to_string());
+  seal(Encryptor::with_user_passphrase(passphrase), value, armor)
+}
+
+/// Encrypt `value` to the given X25519 recipients, any of which can decrypt it.
+pub fn encrypt_to_recipients(value: &[u8], recipients: &[Recipient], armor: bool) -> Result<Vec<u8>> {
+  let recipients = recipients.iter()
+    .map(|recipient| {
+      recipient.to_string().parse::<age::x25519::Recipient>()
+        .map_err(|err| Error::InvalidValue(format!("Invalid recipient {}: {}", recipient, err)))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  let encryptor = Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+    .map_err(|err| Error::InvalidValue(format!("Cannot encrypt to recipients: {}", err)))?;
+  seal(encryptor, value, armor)
+}
+
+/// Whether the given age file is encrypted with a passphrase rather than to recipients.
+pub fn is_passphrase_encrypted(contents: &[u8]) -> Result<bool> {
+  Ok(Decryptor::new_buffered(ArmoredReader::new(contents)).map_err(decrypt_error)?.is_scrypt())
+}
+
+/// Decrypt an age file, armored or not, with the passphrase or X25519 identities of the credential.
+pub fn decrypt(contents: &[u8], credential: &Credential) -> Result<Vec<u8>> {
+  let decryptor = Decryptor::new_buffered(ArmoredReader::new(contents)).map_err(decrypt_error)?;
+  let mut reader = match credential {
+    Credential::Password(passphrase) => {
+      let identity = age::scrypt::Identity::new(SecretString::from(passphrase.clone()));
+      decryptor.decrypt(iter::once(&identity as &dyn age::Identity))
+    },
+    Credential::Identities(identities) => {
+      let identities = identities.iter()
+        .map(|identity| identity.to_string().parse::<age::x25519::Identity>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|err| Error::InvalidValue(format!("Invalid identity: {}", err)))?;
+      decryptor.decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+    },
+    Credential::MasterKey(_) | Credential::Keyfile(_) => {
+      return Err(Error::InvalidValue("age files can only be decrypted with a passphrase or identities".to_string()));
+    },
+  }.map_err(decrypt_error)?;
+
+  // the payload is authenticated chunk by chunk, so tampering only surfaces while reading it
+  let mut value = vec![];
+  reader.read_to_end(&mut value)
+    .map_err(|_| Error::WrongPassword)?;
+  Ok(value)
+}
+
+fn seal(encryptor: Encryptor, value: &[u8], armor: bool) -> Result<Vec<u8>> {
+  let format = if armor { Format::AsciiArmor } else { Format::Binary };
+  let mut contents = vec![];
+  ArmoredWriter::wrap_output(&mut contents, format)
+    .and_then(|output| encryptor.wrap_output(output))
+    .and_then(|mut writer| writer.write_all(value).map(|_| writer))
+    .and_then(|writer| writer.finish())
+    .and_then(|output| output.finish())
+    .map_err(|err| Error::Other(format!("Failed to encrypt: {}", err)))?;
+  Ok(contents)
+}
+
+fn decrypt_error(err: DecryptError) -> Error {
+  match err {
+    // age cannot tell a wrong passphrase or identity from a tampered file either
+    DecryptError::NoMatchingKeys
+    | DecryptError::KeyDecryptionFailed
+    | DecryptError::InvalidMac
+    | DecryptError::DecryptionFailed => Error::WrongPassword,
+    err => Error::InvalidValue(format!("Invalid age file: {}", err)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use sha2::{Digest, Sha256};
+
+  use crate::recipient::Identity;
+
+  // vectors from the age test kit (https://github.com/C2SP/CCTV/tree/main/age), whose payloads are
+  // only known by their SHA-256 digest
+  const PAYLOAD_DIGEST: &str = "013f54400c82da08037759ada907a8b864e97de81c088a182062c4b5622fd2ab";
+  const SCRYPT_HEADER: &str = "age-encryption.org/v1
+-> scrypt rF0/NwblUHHTpgQgRpe5CQ 10
+gUjEymFKMVXQEKdMMHL24oYexjE3TIC0O0zGSqJ2aUY
+--- IOXiQYStkoT1mvZW2tFOqZdhRVvj58egABx/sWfZQbc
+";
+  const SCRYPT_PAYLOAD: &str = "1b35c6e687dd00da3ac379ac9f742c21fd185a1b9e3ded739d14ac6a9a50124db866d8";
+  const ARMORED_IDENTITY: &str = "AGE-SECRET-KEY-1XMWWC06LY3EE5RYTXM9MFLAZ2U56JJJ36S0MYPDRWSVLUL66MV4QX3S7F6";
+  const ARMORED: &str = "-----BEGIN AGE ENCRYPTED FILE-----
+YWdlLWVuY3J5cHRpb24ub3JnL3YxCi0+IFgyNTUxOSBURWlGMHlwcXIrYnB2Y3FY
+TnlDVkpwTDdPdXdQZFZ3UEw3S1FFYkZET0NjCkVtRUNBRWNLTituL1ZzOVNiV2lW
+K0h1MHIrRThSNzdEZFdZeWQ4M253N1UKLS0tIFZuKzU0anFpaVVDRStXWmNFVlkz
+ZjFzcUhqbHUvejFMQ1EvVDdYbTdxSTAK7s9ix86RtDMnTmjU8vkTTLdMW/73vqpS
+yPC8DpksHoMx+2Y=
+-----END AGE ENCRYPTED FILE-----
+";
+
+  fn password(password: &str) -> Credential {
+    Credential::Password(password.to_string())
+  }
+
+  #[test]
+  fn round_trips_passphrases() {
+    for armor in [false, true] {
+      let contents = encrypt_with_passphrase(b"secret", "hunter2", armor).unwrap();
+      assert_eq!(contents.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"), armor);
+      assert!(is_passphrase_encrypted(&contents).unwrap());
+      assert_eq!(decrypt(&contents, &password("hunter2")).unwrap(), b"secret");
+      assert!(matches!(decrypt(&contents, &password("hunter3")), Err(Error::WrongPassword)));
+    }
+  }
+
+  #[test]
+  fn round_trips_recipients() {
+    let identities = [Identity::generate(), Identity::generate()];
+    let recipients = identities.iter().map(Identity::to_public).collect::<Vec<_>>();
+    for armor in [false, true] {
+      let contents = encrypt_to_recipients(b"secret", &recipients, armor).unwrap();
+      assert_eq!(contents.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"), armor);
+      assert!(!is_passphrase_encrypted(&contents).unwrap());
+      for identity in &identities {
+        assert_eq!(decrypt(&contents, &Credential::Identities(vec![identity.clone()])).unwrap(), b"secret");
+      }
+      let other = Credential::Identities(vec![Identity::generate()]);
+      assert!(matches!(decrypt(&contents, &other), Err(Error::WrongPassword)));
+      assert!(matches!(decrypt(&contents, &Credential::Keyfile(vec![0; 32])), Err(Error::InvalidValue(_))));
+    }
+  }
+
+  #[test]
+  fn decrypts_age_files() {
+    let contents = [SCRYPT_HEADER.as_bytes(), &hex::decode(SCRYPT_PAYLOAD).unwrap()].concat();
+    assert!(is_passphrase_encrypted(&contents).unwrap());
+    let value = decrypt(&contents, &password("password")).unwrap();
+    assert_eq!(hex::encode(Sha256::digest(value)), PAYLOAD_DIGEST);
+
+    let identity = Credential::Identities(vec![ARMORED_IDENTITY.parse().unwrap()]);
+    assert!(!is_passphrase_encrypted(ARMORED.as_bytes()).unwrap());
+    let value = decrypt(ARMORED.as_bytes(), &identity).unwrap();
+    assert_eq!(hex::encode(Sha256::digest(value)), PAYLOAD_DIGEST);
+  }
+
+  #[test]
+  fn rejects_tampered_files() {
+    let mut contents = encrypt_with_passphrase(b"secret", "hunter2", false).unwrap();
+    let last = contents.len() - 1;
+    contents[last] ^= 1;
+    assert!(matches!(decrypt(&contents, &password("hunter2")), Err(Error::WrongPassword)));
+    assert!(matches!(decrypt(b"not an age file", &password("hunter2")), Err(Error::InvalidValue(_))));
+  }
+}
@@ -0,0 +1,363 @@
+//! Agent caching decrypted secrets in memory for a limited time, akin to `ssh-agent`. Clients talk
+//! to it over a Unix domain socket, one JSON request & response per connection.
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Timeout for a single request, so a stuck client cannot block the agent.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+  Unlock { path: PathBuf, secret: String, ttl: u64 },
+  Get { path: PathBuf },
+  Lock { path: PathBuf },
+  LockAll,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response {
+  Ok,
+  Secret { secret: String },
+  Locked,
+  Error { message: String },
+}
+
+struct Entry {
+  secret: Vec<u8>,
+  expires: Instant,
+}
+
+impl Drop for Entry {
+  fn drop(&mut self) {
+    self.secret.fill(0);
+  }
+}
+
+type Entries = Arc<Mutex<HashMap<PathBuf, Entry>>>;
+
+/// The agent process, holding unlocked secrets by the canonical path of their keystore.
+pub struct Agent {
+  listener: UnixListener,
+  socket: PathBuf,
+  entries: Entries,
+}
+
+impl Agent {
+  /// Listen on the given socket, which is only accessible by the current user. A stale socket left
+  /// behind by a previous agent is replaced.
+  pub fn bind(socket: impl Into<PathBuf>) -> Result<Self> {
+    let socket = socket.into();
+    let context = || format!("Could not listen on {}", socket.to_string_lossy());
+
+    if socket.exists() {
+      if UnixStream::connect(&socket).is_ok() {
+        return Err(Error::Other(format!("An agent is already listening on {}", socket.to_string_lossy())));
+      }
+      fs::remove_file(&socket).map_err(|err| Error::io(context(), err))?;
+    }
+
+    let listener = UnixListener::bind(&socket).map_err(|err| Error::io(context(), err))?;
+    fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))
+      .map_err(|err| Error::io(context(), err))?;
+
+    Ok(Self {
+      listener,
+      socket,
+      entries: Default::default(),
+    })
+  }
+
+  pub fn socket(&self) -> &Path {
+    &self.socket
+  }
+
+  /// Serve requests until the process is terminated. Expired secrets are wiped every second.
+  pub fn run(self) -> Result<()> {
+    let entries = self.entries.clone();
+    thread::spawn(move || loop {
+      thread::sleep(Duration::from_secs(1));
+      let now = Instant::now();
+      entries.lock().unwrap().retain(|_, entry| entry.expires > now);
+    });
+
+    for stream in self.listener.incoming() {
+      // a misbehaving client must not take down the agent
+      let Ok(stream) = stream else { continue };
+      let _ = self.serve(stream);
+    }
+    Ok(())
+  }
+
+  fn serve(&self, stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str(&line) {
+      Ok(request) => self.handle(request),
+      Err(err) => Response::Error { message: format!("Invalid request: {}", err) },
+    };
+    write_message(&stream, &response)
+  }
+
+  fn handle(&self, request: Request) -> Response {
+    let mut entries = self.entries.lock().unwrap();
+    match request {
+      Request::Unlock { path, secret, ttl } => match hex::decode(secret) {
+        Ok(secret) => match Instant::now().checked_add(Duration::from_secs(ttl)) {
+          Some(expires) => {
+            entries.insert(path, Entry { secret, expires });
+            Response::Ok
+          },
+          None => Response::Error { message: format!("Invalid ttl: {}s", ttl) },
+        },
+        Err(err) => Response::Error { message: format!("Invalid secret: {}", err) },
+      },
+      Request::Get { path } => match entries.get(&path) {
+        Some(entry) if entry.expires > Instant::now() => Response::Secret { secret: hex::encode(&entry.secret) },
+        _ => Response::Locked,
+      },
+      Request::Lock { path } => match entries.remove(&path) {
+        Some(_) => Response::Ok,
+        None => Response::Locked,
+      },
+      Request::LockAll => {
+        entries.clear();
+        Response::Ok
+      },
+    }
+  }
+}
+
+impl Drop for Agent {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.socket);
+  }
+}
+
+/// Client of an [`Agent`]. Keystores are identified by their path.
+#[derive(Clone, Debug)]
+pub struct AgentClient {
+  socket: PathBuf,
+}
+
+impl AgentClient {
+  pub fn new(socket: impl Into<PathBuf>) -> Self {
+    Self { socket: socket.into() }
+  }
+
+  /// The default socket at `$XDG_RUNTIME_DIR/kayring-agent.sock`, or `~/.kayring-agent.sock`.
+  pub fn default_socket() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+      return Ok(PathBuf::from(dir).join("kayring-agent.sock"));
+    }
+    let homedir = home::home_dir()
+      .ok_or_else(|| Error::Other("Could not determine the root directory".to_string()))?;
+    Ok(homedir.join(".kayring-agent.sock"))
+  }
+
+  pub fn socket(&self) -> &Path {
+    &self.socket
+  }
+
+  /// Whether an agent is listening on the socket.
+  pub fn is_running(&self) -> bool {
+    UnixStream::connect(&self.socket).is_ok()
+  }
+
+  /// Hand the decrypted secret of the keystore at `path` to the agent for the given time.
+  pub fn unlock(&self, path: &Path, secret: &[u8], ttl: Duration) -> Result<()> {
+    let request = Request::Unlock {
+      path: canonical(path)?,
+      secret: hex::encode(secret),
+      ttl: ttl.as_secs(),
+    };
+    match self.request(&request)? {
+      Response::Ok => Ok(()),
+      response => Err(unexpected(response)),
+    }
+  }
+
+  /// The secret of the keystore at `path` if it is unlocked. Returns `None` if it is not, or if no
+  /// agent is running.
+  pub fn get(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+    let Some(stream) = self.connect()? else { return Ok(None) };
+    match self.exchange(stream, &Request::Get { path: canonical(path)? })? {
+      Response::Secret { secret } => hex::decode(secret)
+        .map(Some)
+        .map_err(|err| Error::Other(format!("Invalid response from agent: {}", err))),
+      Response::Locked => Ok(None),
+      response => Err(unexpected(response)),
+    }
+  }
+
+  /// Remove the secret of the keystore at `path` from the agent. Returns whether it was unlocked.
+  pub fn lock(&self, path: &Path) -> Result<bool> {
+    match self.request(&Request::Lock { path: canonical(path)? })? {
+      Response::Ok => Ok(true),
+      Response::Locked => Ok(false),
+      response => Err(unexpected(response)),
+    }
+  }
+
+  /// Remove the secret of the keystore at `path` from the agent, if one is running, e.g. because the
+  /// keystore was rewritten & the agent would serve a stale secret otherwise.
+  pub fn forget(&self, path: &Path) -> Result<()> {
+    let Some(stream) = self.connect()? else { return Ok(()) };
+    match self.exchange(stream, &Request::Lock { path: canonical(path)? })? {
+      Response::Ok | Response::Locked => Ok(()),
+      response => Err(unexpected(response)),
+    }
+  }
+
+  /// Remove all secrets from the agent.
+  pub fn lock_all(&self) -> Result<()> {
+    match self.request(&Request::LockAll)? {
+      Response::Ok => Ok(()),
+      response => Err(unexpected(response)),
+    }
+  }
+
+  /// Connect to the agent, or `None` if none is running.
+  fn connect(&self) -> Result<Option<UnixStream>> {
+    match UnixStream::connect(&self.socket) {
+      Ok(stream) => Ok(Some(stream)),
+      Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => Ok(None),
+      Err(err) => Err(Error::io(self.context(), err)),
+    }
+  }
+
+  fn request(&self, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(&self.socket).map_err(|err| Error::io(self.context(), err))?;
+    self.exchange(stream, request)
+  }
+
+  fn exchange(&self, stream: UnixStream, request: &Request) -> Result<Response> {
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|err| Error::io(self.context(), err))?;
+
+    write_message(&stream, request).map_err(|err| Error::io(self.context(), err))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).map_err(|err| Error::io(self.context(), err))?;
+    serde_json::from_str(&line)
+      .map_err(|err| Error::Other(format!("Invalid response from agent: {}", err)))
+  }
+
+  fn context(&self) -> String {
+    format!("Could not talk to the agent at {}", self.socket.to_string_lossy())
+  }
+}
+
+fn write_message(mut stream: &UnixStream, message: &impl Serialize) -> io::Result<()> {
+  let mut line = serde_json::to_string(message).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+  line.push('\n');
+  stream.write_all(line.as_bytes())
+}
+
+fn canonical(path: &Path) -> Result<PathBuf> {
+  fs::canonicalize(path)
+    .map_err(|err| Error::io(format!("Could not resolve {}", path.to_string_lossy()), err))
+}
+
+fn unexpected(response: Response) -> Error {
+  match response {
+    Response::Error { message } => Error::Other(format!("Agent error: {}", message)),
+    _ => Error::Other("Unexpected response from agent".to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn spawn_agent(dir: &Path) -> AgentClient {
+    let agent = Agent::bind(dir.join("agent.sock")).unwrap();
+    let client = AgentClient::new(agent.socket());
+    thread::spawn(move || agent.run());
+    client
+  }
+
+  #[test]
+  fn holds_secrets_until_locked() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = spawn_agent(dir.path());
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&a, b"").unwrap();
+    fs::write(&b, b"").unwrap();
+
+    assert!(client.is_running());
+    assert_eq!(client.get(&a).unwrap(), None);
+    client.unlock(&a, &[1, 2, 3], Duration::from_secs(60)).unwrap();
+    client.unlock(&b, &[4, 5, 6], Duration::from_secs(60)).unwrap();
+    assert_eq!(client.get(&a).unwrap(), Some(vec![1, 2, 3]));
+    // keystores are identified by their canonical path
+    assert_eq!(client.get(&dir.path().join(".").join("a")).unwrap(), Some(vec![1, 2, 3]));
+
+    assert!(client.lock(&a).unwrap());
+    assert!(!client.lock(&a).unwrap());
+    assert_eq!(client.get(&a).unwrap(), None);
+    client.forget(&b).unwrap();
+    client.forget(&b).unwrap();
+    assert_eq!(client.get(&b).unwrap(), None);
+
+    client.unlock(&a, &[1], Duration::from_secs(60)).unwrap();
+    client.lock_all().unwrap();
+    assert_eq!(client.get(&a).unwrap(), None);
+  }
+
+  #[test]
+  fn expires_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = spawn_agent(dir.path());
+    let path = dir.path().join("a");
+    fs::write(&path, b"").unwrap();
+
+    client.unlock(&path, &[1], Duration::ZERO).unwrap();
+    assert_eq!(client.get(&path).unwrap(), None);
+    assert!(client.unlock(&path, &[1], Duration::from_secs(u64::MAX)).is_err());
+  }
+
+  #[test]
+  fn refuses_to_replace_live_agent() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = spawn_agent(dir.path());
+    assert!(Agent::bind(client.socket()).is_err());
+    assert!(client.is_running());
+  }
+
+  #[test]
+  fn replaces_stale_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("agent.sock");
+    drop(UnixListener::bind(&socket).unwrap());
+    let agent = Agent::bind(&socket).unwrap();
+    assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+    drop(agent);
+    assert!(!socket.exists());
+  }
+
+  #[test]
+  fn treats_missing_agent_as_locked() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = AgentClient::new(dir.path().join("agent.sock"));
+    let path = dir.path().join("a");
+    fs::write(&path, b"").unwrap();
+    assert!(!client.is_running());
+    assert_eq!(client.get(&path).unwrap(), None);
+    client.forget(&path).unwrap();
+    assert!(client.lock(&path).is_err());
+  }
+}
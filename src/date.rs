@@ -0,0 +1,145 @@
+//! Current time & conversion between UTC calendar dates & unix timestamps, using Howard Hinnant's
+//! `days_from_civil` algorithm.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const MAX_YEAR: u64 = 9999;
+
+/// Current time in seconds since the unix epoch.
+pub(crate) fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+}
+
+/// Parse `YYYY-MM-DD` as the last second of that day, `YYYY-MM-DDTHH:MM:SSZ`, or a unix timestamp.
+/// Dates past the year 9999 are rejected.
+pub(crate) fn parse(value: &str) -> Option<u64> {
+  if let Ok(secs) = value.parse() {
+    return Some(secs);
+  }
+
+  let (date, time) = match value.split_once('T') {
+    Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+    None => (value, None),
+  };
+
+  let mut parts = date.splitn(3, '-');
+  let year: u64 = parts.next()?.parse().ok()?;
+  let month: u64 = parts.next()?.parse().ok()?;
+  let day: u64 = parts.next()?.parse().ok()?;
+  if !(1970..=MAX_YEAR).contains(&year) || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+    return None;
+  }
+  let days = days_from_civil(year, month, day);
+
+  let secs = match time {
+    Some(time) => {
+      let mut parts = time.splitn(3, ':');
+      let hours: u64 = parts.next()?.parse().ok()?;
+      let minutes: u64 = parts.next()?.parse().ok()?;
+      let seconds: u64 = parts.next()?.parse().ok()?;
+      if hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+      }
+      hours * 3600 + minutes * 60 + seconds
+    },
+    None => SECS_PER_DAY - 1,
+  };
+  Some(days * SECS_PER_DAY + secs)
+}
+
+/// Format a unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`.
+pub(crate) fn format(secs: u64) -> String {
+  let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+  let secs = secs % SECS_PER_DAY;
+  format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn is_leap_year(year: u64) -> bool {
+  year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+  match month {
+    2 if is_leap_year(year) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+/// Days since the unix epoch of the given date, which must not precede it.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year / 400;
+  let yoe = year - era * 400;
+  let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+  let days = days + 719468;
+  let era = days / 146097;
+  let doe = days - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_dates() {
+    assert_eq!(parse("0"), Some(0));
+    assert_eq!(parse("1700000000"), Some(1_700_000_000));
+    assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(parse("2023-11-14T22:13:20Z"), Some(1_700_000_000));
+    // bare dates mean the end of that day
+    assert_eq!(parse("1970-01-01"), Some(SECS_PER_DAY - 1));
+    assert_eq!(parse("2023-11-14"), Some(1_700_006_399));
+  }
+
+  #[test]
+  fn handles_leap_years() {
+    assert_eq!(parse("2024-02-29"), Some(1_709_251_199));
+    assert_eq!(parse("2000-02-29T12:00:00Z"), Some(951_825_600));
+    assert_eq!(parse("2023-02-29"), None);
+    assert_eq!(parse("2100-02-29"), None);
+    assert_eq!(parse("2024-03-01T00:00:00Z"), Some(1_709_251_200));
+  }
+
+  #[test]
+  fn bounds_years() {
+    assert_eq!(parse("9999-12-31"), Some(253_402_300_799));
+    assert_eq!(parse("10000-01-01"), None);
+    assert_eq!(parse("1969-12-31"), None);
+    assert_eq!(parse("18446744073709551615-01-01"), None);
+  }
+
+  #[test]
+  fn rejects_malformed_dates() {
+    for value in ["", "2024-01", "2024-13-01", "2024-04-31", "2024-01-00", "2024-01-01T24:00:00Z", "2024-01-01T12:00:00",
+      "2024-01-01T12:60:00Z", "2024-01-01 12:00:00", "-1"] {
+      assert_eq!(parse(value), None, "{}", value);
+    }
+  }
+
+  #[test]
+  fn formats_dates() {
+    assert_eq!(format(0), "1970-01-01 00:00:00 UTC");
+    assert_eq!(format(1_709_251_199), "2024-02-29 23:59:59 UTC");
+    assert_eq!(format(253_402_300_799), "9999-12-31 23:59:59 UTC");
+    for value in ["2000-02-29T12:00:00Z", "2024-12-31T23:59:59Z", "2100-03-01T00:00:01Z"] {
+      let secs = parse(value).unwrap();
+      assert_eq!(parse(&format(secs).replace(' ', "T").replace("TUTC", "Z")), Some(secs));
+    }
+  }
+}
@@ -0,0 +1,355 @@
+//! EIP-712 typed structured data hashing as used by `eth_signTypedData_v4`.
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::error::{Error, Result};
+use crate::eth::keccak256;
+
+/// An EIP-712 payload in the JSON format of `eth_signTypedData_v4`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+  pub types: BTreeMap<String, Vec<Field>>,
+  pub primary_type: String,
+  pub domain: Map<String, Value>,
+  #[serde(default)]
+  pub message: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Field {
+  pub name: String,
+  #[serde(rename = "type")]
+  pub ty: String,
+}
+
+const DOMAIN: &str = "EIP712Domain";
+
+impl TypedData {
+  pub fn from_json(json: &str) -> Result<Self> {
+    let mut data: TypedData = serde_json::from_str(json).map_err(invalid)?;
+    if !data.types.contains_key(DOMAIN) {
+      // infer the domain type from the fields present, in the canonical order
+      let fields = [
+        ("name", "string"),
+        ("version", "string"),
+        ("chainId", "uint256"),
+        ("verifyingContract", "address"),
+        ("salt", "bytes32"),
+      ];
+      let domain = fields.iter()
+        .filter(|(name, _)| data.domain.contains_key(*name))
+        .map(|(name, ty)| Field { name: name.to_string(), ty: ty.to_string() })
+        .collect();
+      data.types.insert(DOMAIN.to_string(), domain);
+    }
+    Ok(data)
+  }
+
+  pub fn domain_separator(&self) -> Result<[u8; 32]> {
+    self.hash_struct(DOMAIN, &self.domain)
+  }
+
+  /// Hash to be signed: `keccak256(0x1901 ‖ domainSeparator ‖ hashStruct(message))`.
+  pub fn signing_hash(&self) -> Result<[u8; 32]> {
+    let mut data = vec![0x19, 0x01];
+    data.extend(self.domain_separator()?);
+    if self.primary_type != DOMAIN {
+      data.extend(self.hash_struct(&self.primary_type, &self.message)?);
+    }
+    Ok(keccak256(data))
+  }
+
+  /// `keccak256(encodeType(ty))`.
+  pub fn type_hash(&self, ty: &str) -> Result<[u8; 32]> {
+    Ok(keccak256(self.encode_type(ty)?))
+  }
+
+  /// The type's signature, followed by those of all struct types it references in alphabetical order.
+  pub fn encode_type(&self, ty: &str) -> Result<String> {
+    let mut deps = BTreeSet::new();
+    self.collect_deps(ty, &mut deps)?;
+    deps.remove(ty);
+
+    let mut encoded = String::new();
+    for name in std::iter::once(ty).chain(deps.iter().map(String::as_str)) {
+      let fields = self.fields(name)?.iter()
+        .map(|field| format!("{} {}", field.ty, field.name))
+        .collect::<Vec<_>>();
+      encoded += &format!("{}({})", name, fields.join(","));
+    }
+    Ok(encoded)
+  }
+
+  pub fn hash_struct(&self, ty: &str, value: &Map<String, Value>) -> Result<[u8; 32]> {
+    let mut data = self.type_hash(ty)?.to_vec();
+    for field in self.fields(ty)? {
+      let value = value.get(&field.name).unwrap_or(&Value::Null);
+      data.extend(self.encode_value(&field.ty, value)
+        .map_err(|err| Error::InvalidValue(format!("{} (field {}.{})", err, ty, field.name)))?);
+    }
+    Ok(keccak256(data))
+  }
+
+  fn fields(&self, ty: &str) -> Result<&[Field]> {
+    self.types.get(ty)
+      .map(Vec::as_slice)
+      .ok_or_else(|| invalid(format!("undefined type {}", ty)))
+  }
+
+  fn collect_deps(&self, ty: &str, deps: &mut BTreeSet<String>) -> Result<()> {
+    if deps.contains(ty) {
+      return Ok(());
+    }
+    deps.insert(ty.to_string());
+    for field in self.fields(ty)? {
+      let base = field.ty.split('[').next().unwrap();
+      if self.types.contains_key(base) {
+        self.collect_deps(base, deps)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn encode_value(&self, ty: &str, value: &Value) -> Result<[u8; 32]> {
+    if let Some(elem_ty) = ty.strip_suffix(']') {
+      let (elem_ty, len) = elem_ty.rsplit_once('[').ok_or_else(|| invalid(format!("invalid type {}", ty)))?;
+      let items = value.as_array().ok_or_else(|| invalid("expected array"))?;
+      if !len.is_empty() && len.parse::<usize>().ok() != Some(items.len()) {
+        return Err(invalid(format!("expected {} items", len)));
+      }
+      let mut data = vec![];
+      for item in items {
+        data.extend(self.encode_value(elem_ty, item)?);
+      }
+      return Ok(keccak256(data));
+    }
+
+    if self.types.contains_key(ty) {
+      let value = value.as_object().ok_or_else(|| invalid("expected object"))?;
+      return self.hash_struct(ty, value);
+    }
+
+    match ty {
+      "string" => Ok(keccak256(value.as_str().ok_or_else(|| invalid("expected string"))?)),
+      "bytes" => Ok(keccak256(unhex(value)?)),
+      "bool" => {
+        let mut word = [0u8; 32];
+        word[31] = value.as_bool().ok_or_else(|| invalid("expected bool"))? as u8;
+        Ok(word)
+      },
+      "address" => {
+        let bytes = unhex(value)?;
+        if bytes.len() != 20 {
+          return Err(invalid("address must be 20 bytes"));
+        }
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&bytes);
+        Ok(word)
+      },
+      _ => {
+        if let Some(size) = ty.strip_prefix("bytes") {
+          let size = parse_size(ty, size, 1..=32)?;
+          let bytes = unhex(value)?;
+          if bytes.len() != size {
+            return Err(invalid(format!("{} must be {} bytes", ty, size)));
+          }
+          let mut word = [0u8; 32];
+          word[..size].copy_from_slice(&bytes);
+          Ok(word)
+        } else if let Some(bits) = ty.strip_prefix("uint") {
+          let bits = parse_int_bits(ty, bits)?;
+          let (negative, magnitude) = parse_int(value)?;
+          if negative || bit_len(&magnitude) > bits {
+            return Err(invalid(format!("value out of range for {}", ty)));
+          }
+          Ok(magnitude)
+        } else if let Some(bits) = ty.strip_prefix("int") {
+          let bits = parse_int_bits(ty, bits)?;
+          let (negative, magnitude) = parse_int(value)?;
+          if !negative {
+            if bit_len(&magnitude) >= bits {
+              return Err(invalid(format!("value out of range for {}", ty)));
+            }
+            return Ok(magnitude);
+          }
+          let word = negate(&magnitude);
+          // the magnitude minus one, i.e. !(-magnitude), must fit into the positive range
+          if bit_len(&word.map(|b| !b)) >= bits {
+            return Err(invalid(format!("value out of range for {}", ty)));
+          }
+          Ok(word)
+        } else {
+          Err(invalid(format!("undefined type {}", ty)))
+        }
+      },
+    }
+  }
+}
+
+fn parse_size(ty: &str, size: &str, range: std::ops::RangeInclusive<usize>) -> Result<usize> {
+  size.parse().ok()
+    .filter(|size| range.contains(size))
+    .ok_or_else(|| invalid(format!("invalid type {}", ty)))
+}
+
+fn parse_int_bits(ty: &str, bits: &str) -> Result<usize> {
+  if bits.is_empty() {
+    return Ok(256);
+  }
+  let bits = parse_size(ty, bits, 8..=256)?;
+  if bits % 8 != 0 {
+    return Err(invalid(format!("invalid type {}", ty)));
+  }
+  Ok(bits)
+}
+
+/// Parse an integer given as JSON number, decimal or hex string into its sign & 256 bit magnitude.
+fn parse_int(value: &Value) -> Result<(bool, [u8; 32])> {
+  let text = match value {
+    Value::Number(num) if num.is_i64() || num.is_u64() => num.to_string(),
+    Value::String(text) => text.trim().to_string(),
+    _ => return Err(invalid("expected integer")),
+  };
+  let (negative, digits) = match text.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, text.as_str()),
+  };
+  let (radix, digits) = match digits.strip_prefix("0x") {
+    Some(digits) => (16, digits),
+    None => (10, digits),
+  };
+  if digits.is_empty() {
+    return Err(invalid(format!("invalid integer {}", text)));
+  }
+
+  let mut word = [0u8; 32];
+  for c in digits.chars() {
+    let digit = c.to_digit(radix).ok_or_else(|| invalid(format!("invalid integer {}", text)))?;
+    let mut carry = digit;
+    for byte in word.iter_mut().rev() {
+      let acc = *byte as u32 * radix + carry;
+      *byte = acc as u8;
+      carry = acc >> 8;
+    }
+    if carry != 0 {
+      return Err(invalid(format!("integer {} exceeds 256 bits", text)));
+    }
+  }
+  Ok((negative && word != [0u8; 32], word))
+}
+
+fn bit_len(word: &[u8; 32]) -> usize {
+  match word.iter().position(|b| *b != 0) {
+    Some(i) => (32 - i) * 8 - word[i].leading_zeros() as usize,
+    None => 0,
+  }
+}
+
+/// Two's complement of a 256 bit word.
+fn negate(word: &[u8; 32]) -> [u8; 32] {
+  let mut result = word.map(|b| !b);
+  for byte in result.iter_mut().rev() {
+    let (sum, overflow) = byte.overflowing_add(1);
+    *byte = sum;
+    if !overflow {
+      break;
+    }
+  }
+  result
+}
+
+fn unhex(value: &Value) -> Result<Vec<u8>> {
+  let text = value.as_str().ok_or_else(|| invalid("expected hex string"))?;
+  hex::decode(text.trim_start_matches("0x")).map_err(invalid)
+}
+
+fn invalid(err: impl ToString) -> Error {
+  Error::InvalidValue(format!("Invalid typed data: {}", err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eth::{address, sign_hash, to_checksum_address};
+
+  // "Mail" example of EIP-712
+  const MAIL: &str = r#"{
+    "types": {
+      "EIP712Domain": [
+        {"name": "name", "type": "string"},
+        {"name": "version", "type": "string"},
+        {"name": "chainId", "type": "uint256"},
+        {"name": "verifyingContract", "type": "address"}
+      ],
+      "Person": [
+        {"name": "name", "type": "string"},
+        {"name": "wallet", "type": "address"}
+      ],
+      "Mail": [
+        {"name": "from", "type": "Person"},
+        {"name": "to", "type": "Person"},
+        {"name": "contents", "type": "string"}
+      ]
+    },
+    "primaryType": "Mail",
+    "domain": {
+      "name": "Ether Mail",
+      "version": "1",
+      "chainId": 1,
+      "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+    },
+    "message": {
+      "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+      "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+      "contents": "Hello, Bob!"
+    }
+  }"#;
+
+  #[test]
+  fn hashes_mail_example() {
+    let data = TypedData::from_json(MAIL).unwrap();
+    assert_eq!(data.encode_type("Mail").unwrap(), "Mail(Person from,Person to,string contents)Person(string name,address wallet)");
+    assert_eq!(
+      hex::encode(data.type_hash("Mail").unwrap()),
+      "a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2",
+    );
+    assert_eq!(
+      hex::encode(data.hash_struct("Mail", &data.message).unwrap()),
+      "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e",
+    );
+    assert_eq!(
+      hex::encode(data.domain_separator().unwrap()),
+      "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f",
+    );
+    assert_eq!(
+      hex::encode(data.signing_hash().unwrap()),
+      "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2",
+    );
+  }
+
+  #[test]
+  fn signs_mail_example() {
+    let privkey = keccak256("cow");
+    assert_eq!(to_checksum_address(&address(&privkey).unwrap()), "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826");
+
+    let hash = TypedData::from_json(MAIL).unwrap().signing_hash().unwrap();
+    let sig = sign_hash(&privkey, &hash).unwrap();
+    assert_eq!(hex::encode(sig.r), "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d");
+    assert_eq!(hex::encode(sig.s), "07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562");
+    assert_eq!(sig.to_bytes()[64], 28);
+  }
+
+  #[test]
+  fn infers_domain_type() {
+    let mut data: Value = serde_json::from_str(MAIL).unwrap();
+    data["types"].as_object_mut().unwrap().remove(DOMAIN);
+    let data = TypedData::from_json(&data.to_string()).unwrap();
+    assert_eq!(
+      hex::encode(data.domain_separator().unwrap()),
+      "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f",
+    );
+  }
+}
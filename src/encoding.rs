@@ -0,0 +1,136 @@
+//! Representations of secrets as given by the user, so they can be reproduced exactly.
+use std::fmt;
+use std::str::FromStr;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::error::{Error, Result};
+
+/// Representation of a secret. Recorded in the keystore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+  /// `0x` prefixed hex string, e.g. a private key.
+  #[default]
+  Hex,
+  /// UTF-8 string, e.g. an API token or RPC URL.
+  Utf8,
+  /// Standard base64 with padding.
+  Base64,
+  /// Base58 as used by Bitcoin & Solana.
+  Base58,
+  /// Contents of a binary file, stored as is.
+  File,
+}
+
+impl Encoding {
+  pub const ALL: [Encoding; 5] = [Encoding::Hex, Encoding::Utf8, Encoding::Base64, Encoding::Base58, Encoding::File];
+
+  /// Decode the given representation into the secret.
+  pub fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+    let text = || std::str::from_utf8(value)
+      .map_err(|_| Error::InvalidValue(format!("Value must be valid UTF-8 for encoding {}", self)));
+    match self {
+      Encoding::Hex => {
+        let value = text()?.strip_prefix("0x")
+          .ok_or_else(|| Error::InvalidValue("Value must be a hex string starting with '0x'".to_string()))?;
+        hex::decode(value)
+          .map_err(|_| Error::InvalidValue("Value must be a valid hex string".to_string()))
+      },
+      Encoding::Utf8 => Ok(text()?.as_bytes().to_vec()),
+      Encoding::Base64 => BASE64_STANDARD.decode(text()?)
+        .map_err(|_| Error::InvalidValue("Value must be a valid base64 string".to_string())),
+      Encoding::Base58 => bs58::decode(text()?).into_vec()
+        .map_err(|_| Error::InvalidValue("Value must be a valid base58 string".to_string())),
+      Encoding::File => Ok(value.to_vec()),
+    }
+  }
+
+  /// Encode the secret into its original representation.
+  pub fn encode(&self, secret: &[u8]) -> Result<Vec<u8>> {
+    match self {
+      Encoding::Hex => Ok(format!("0x{}", hex::encode(secret)).into_bytes()),
+      Encoding::Utf8 | Encoding::File => Ok(secret.to_vec()),
+      Encoding::Base64 => Ok(BASE64_STANDARD.encode(secret).into_bytes()),
+      Encoding::Base58 => Ok(bs58::encode(secret).into_string().into_bytes()),
+    }
+  }
+
+  /// Like [`Encoding::encode`], but fails for binary files, which cannot be represented as string.
+  pub fn encode_string(&self, secret: &[u8]) -> Result<String> {
+    if *self == Encoding::File {
+      return Err(Error::InvalidValue("Binary files cannot be represented as string".to_string()));
+    }
+    String::from_utf8(self.encode(secret)?)
+      .map_err(|_| Error::InvalidValue("Secret is not valid UTF-8".to_string()))
+  }
+}
+
+impl FromStr for Encoding {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Encoding::ALL.into_iter()
+      .find(|encoding| encoding.to_string() == s)
+      .ok_or_else(|| Error::InvalidValue(format!("Unknown encoding {}. Expected hex, utf8, base64, base58 or file", s)))
+  }
+}
+
+impl fmt::Display for Encoding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Encoding::Hex => "hex",
+      Encoding::Utf8 => "utf8",
+      Encoding::Base64 => "base64",
+      Encoding::Base58 => "base58",
+      Encoding::File => "file",
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trips() {
+    let cases: [(Encoding, &[u8], &[u8]); 5] = [
+      (Encoding::Hex, b"0xdeadbeef", &[0xde, 0xad, 0xbe, 0xef]),
+      (Encoding::Utf8, "https://rpc.example/\u{1f511}".as_bytes(), "https://rpc.example/\u{1f511}".as_bytes()),
+      (Encoding::Base64, b"3q2+7w==", &[0xde, 0xad, 0xbe, 0xef]),
+      (Encoding::Base58, b"6h8cQN", &[0xde, 0xad, 0xbe, 0xef]),
+      (Encoding::File, &[0, 0xff, 0xfe], &[0, 0xff, 0xfe]),
+    ];
+    for (encoding, encoded, secret) in cases {
+      assert_eq!(encoding.decode(encoded).unwrap(), secret, "{}", encoding);
+      assert_eq!(encoding.encode(secret).unwrap(), encoded, "{}", encoding);
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_values() {
+    assert!(matches!(Encoding::Hex.decode(b"deadbeef"), Err(Error::InvalidValue(_))));
+    assert!(matches!(Encoding::Hex.decode(b"0xdeadbee"), Err(Error::InvalidValue(_))));
+    assert!(matches!(Encoding::Base64.decode(b"3q2+7w"), Err(Error::InvalidValue(_))));
+    assert!(matches!(Encoding::Base58.decode(b"0OIl"), Err(Error::InvalidValue(_))));
+    for encoding in [Encoding::Hex, Encoding::Utf8, Encoding::Base64, Encoding::Base58] {
+      assert!(matches!(encoding.decode(&[0xff]), Err(Error::InvalidValue(_))), "{}", encoding);
+    }
+  }
+
+  #[test]
+  fn encodes_strings() {
+    assert_eq!(Encoding::Hex.encode_string(&[0xde, 0xad]).unwrap(), "0xdead");
+    assert_eq!(Encoding::Utf8.encode_string(b"token").unwrap(), "token");
+    assert!(matches!(Encoding::Utf8.encode_string(&[0xff]), Err(Error::InvalidValue(_))));
+    assert!(matches!(Encoding::File.encode_string(b"text"), Err(Error::InvalidValue(_))));
+  }
+
+  #[test]
+  fn parses_names() {
+    for encoding in Encoding::ALL {
+      assert_eq!(encoding.to_string().parse::<Encoding>().unwrap(), encoding);
+    }
+    assert!(matches!("HEX".parse::<Encoding>(), Err(Error::InvalidValue(_))));
+    assert!(matches!("base32".parse::<Encoding>(), Err(Error::InvalidValue(_))));
+  }
+}
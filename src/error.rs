@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+use std::{fmt, io};
+
+use crate::format::FormatError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong in Kayring. Each variant maps to a distinct process exit code,
+/// which the CLI guarantees to remain stable across releases:
+///
+/// | Code | Variant |
+/// |------|---------|
+/// | 1 | [`Error::Other`] |
+/// | 2 | Reserved for command line usage errors |
+/// | 3 | [`Error::NotFound`] |
+/// | 4 | [`Error::AlreadyExists`] |
+/// | 5 | [`Error::WrongPassword`] |
+/// | 6 | [`Error::Corrupt`] |
+/// | 7 | [`Error::UnknownVersion`] |
+/// | 8 | [`Error::InvalidValue`] |
+/// | 9 | [`Error::Io`] |
+/// | 10 | [`Error::Denied`] |
+#[derive(Debug)]
+pub enum Error {
+  /// No keystore exists by the given name.
+  NotFound(String),
+  /// A keystore by the given name already exists.
+  AlreadyExists(String),
+  /// The password or derivation settings are wrong, or the ciphertext has been tampered with.
+  /// AES-GCM cannot tell these apart.
+  WrongPassword,
+  /// The keystore file is malformed. `path` names the offending file, if known.
+  Corrupt {
+    path: Option<PathBuf>,
+    reason: FormatError,
+  },
+  /// The keystore file has a version this release of Kayring does not know.
+  UnknownVersion(u8),
+  /// A user-supplied value, such as a secret, password or parameter, is invalid.
+  InvalidValue(String),
+  Io {
+    context: String,
+    source: io::Error,
+  },
+  /// The keystore's [`crate::Policy`] forbids reading the secret.
+  Denied(String),
+  Other(String),
+}
+
+impl Error {
+  pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+    Error::Io { context: context.into(), source }
+  }
+
+  /// Attach the path of the offending file to a [`Error::Corrupt`]. Other errors are returned as is.
+  pub fn with_path(self, path: impl Into<PathBuf>) -> Self {
+    match self {
+      Error::Corrupt { reason, .. } => Error::Corrupt { path: Some(path.into()), reason },
+      err => err,
+    }
+  }
+
+  /// Process exit code the CLI reports for this error.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      Error::Other(_) => 1,
+      Error::NotFound(_) => 3,
+      Error::AlreadyExists(_) => 4,
+      Error::WrongPassword => 5,
+      Error::Corrupt { .. } => 6,
+      Error::UnknownVersion(_) => 7,
+      Error::InvalidValue(_) => 8,
+      Error::Io { .. } => 9,
+      Error::Denied(_) => 10,
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::NotFound(name) => write!(f, "No kaystore found for {}", name),
+      Error::AlreadyExists(name) => write!(f, "A kaystore {} already exists. Use --force to overwrite.", name),
+      Error::WrongPassword => write!(f, "Failed to decrypt: wrong password or derivation rounds"),
+      Error::Corrupt { path: Some(path), reason } => write!(f, "Corrupt keystore {}: {}", path.to_string_lossy(), reason),
+      Error::Corrupt { path: None, reason } => write!(f, "Corrupt keystore: {}", reason),
+      Error::UnknownVersion(ver) => write!(f, "Unknown file version {}", ver),
+      Error::InvalidValue(msg) => write!(f, "{}", msg),
+      Error::Io { context, source } => write!(f, "{}: {}", context, source),
+      Error::Denied(msg) => write!(f, "Access denied: {}", msg),
+      Error::Other(msg) => write!(f, "{}", msg),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Corrupt { reason, .. } => Some(reason),
+      Error::Io { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
+
+impl From<FormatError> for Error {
+  fn from(err: FormatError) -> Self {
+    match err {
+      FormatError::UnknownVersion(ver) => Error::UnknownVersion(ver),
+      reason => Error::Corrupt { path: None, reason },
+    }
+  }
+}
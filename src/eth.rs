@@ -0,0 +1,109 @@
+//! Ethereum account helpers.
+use k256::ecdsa::{RecoveryId, SigningKey};
+use sha3::{Digest, Keccak256};
+
+use crate::error::{Error, Result};
+
+pub fn keccak256(data: impl AsRef<[u8]>) -> [u8; 32] {
+  Keccak256::digest(data).into()
+}
+
+/// Parse a raw 32 byte secp256k1 private key.
+pub fn signing_key(privkey: &[u8]) -> Result<SigningKey> {
+  SigningKey::from_slice(privkey)
+    .map_err(|_| Error::InvalidValue("Value is not a valid secp256k1 private key".to_string()))
+}
+
+/// Ethereum address of the given secp256k1 private key.
+pub fn address(privkey: &[u8]) -> Result<[u8; 20]> {
+  let key = signing_key(privkey)?;
+  let pubkey = key.verifying_key().to_encoded_point(false);
+  // skip the 0x04 SEC1 tag of the uncompressed point
+  let hash = keccak256(&pubkey.as_bytes()[1..]);
+  Ok(hash[12..].try_into().unwrap())
+}
+
+/// Recoverable secp256k1 signature as used by Ethereum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+  pub r: [u8; 32],
+  pub s: [u8; 32],
+  /// Parity of the `y` coordinate of `r`, i.e. the recovery id.
+  pub y_parity: bool,
+}
+
+impl Signature {
+  /// 65 byte `r ‖ s ‖ v` encoding with `v` being 27 or 28, as returned by `eth_sign`.
+  pub fn to_bytes(&self) -> [u8; 65] {
+    let mut bytes = [0u8; 65];
+    bytes[..32].copy_from_slice(&self.r);
+    bytes[32..64].copy_from_slice(&self.s);
+    bytes[64] = 27 + self.y_parity as u8;
+    bytes
+  }
+}
+
+/// Sign a 32 byte message hash, e.g. a transaction's signing hash. The signature is normalized to a
+/// low `s` as required by Ethereum.
+pub fn sign_hash(privkey: &[u8], hash: &[u8; 32]) -> Result<Signature> {
+  let key = signing_key(privkey)?;
+  let (mut sig, mut recid) = key.sign_prehash_recoverable(hash)
+    .map_err(|err| Error::Other(format!("Failed to sign: {}", err)))?;
+  if let Some(normalized) = sig.normalize_s() {
+    sig = normalized;
+    recid = RecoveryId::new(!recid.is_y_odd(), recid.is_x_reduced());
+  }
+  let (r, s) = sig.split_bytes();
+  Ok(Signature {
+    r: r.into(),
+    s: s.into(),
+    y_parity: recid.is_y_odd(),
+  })
+}
+
+/// Hash of a message prefixed according to EIP-191 version `0x45`, as signed by `personal_sign`.
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+  let prefix = format!("\x19Ethereum Signed Message:\n{}", message.len());
+  keccak256([prefix.as_bytes(), message].concat())
+}
+
+/// Sign a message according to EIP-191, i.e. `personal_sign`.
+pub fn sign_message(privkey: &[u8], message: &[u8]) -> Result<Signature> {
+  sign_hash(privkey, &message_hash(message))
+}
+
+/// Format an address with the mixed-case checksum of EIP-55.
+pub fn to_checksum_address(address: &[u8; 20]) -> String {
+  let lower = hex::encode(address);
+  let hash = keccak256(lower.as_bytes());
+  let checksummed: String = lower.chars()
+    .enumerate()
+    .map(|(i, c)| {
+      let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+      if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+    })
+    .collect();
+  format!("0x{}", checksummed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hashes_personal_message() {
+    assert_eq!(
+      hex::encode(message_hash(b"hello")),
+      "50b2c43fd39106bafbba0da34fc430e1f91e3c96ea2acee2bc34119f92b37750",
+    );
+  }
+
+  #[test]
+  fn checksums_addresses() {
+    // examples of EIP-55
+    for expected in ["0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"] {
+      let address: [u8; 20] = hex::decode(&expected[2..]).unwrap().try_into().unwrap();
+      assert_eq!(to_checksum_address(&address), expected);
+    }
+  }
+}
@@ -0,0 +1,356 @@
+//! Import & export of the Ethereum Web3 Secret Storage format (keystore v3 JSON) as produced by
+//! geth, Foundry, MetaMask & co.
+use aes::Aes128;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use pbkdf2::hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::{Error, Result};
+use crate::eth::{address, keccak256};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// scrypt cost used by geth for new keystores.
+pub const STANDARD_LOG_N: u8 = 18;
+/// scrypt cost used by geth's `--lightkdf`.
+pub const LIGHT_LOG_N: u8 = 12;
+
+// upper bounds on imported parameters, so a crafted file cannot hang or exhaust memory. scrypt is
+// capped at 1 GiB, 4 times geth's standard cost of `n = 2^18, r = 8`
+const MAX_SCRYPT_N: u64 = 1 << 20;
+const MAX_SCRYPT_R: u32 = 8;
+const MAX_SCRYPT_P: u32 = 16;
+const MAX_PBKDF2_C: u32 = 10_000_000;
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreV3 {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  address: Option<String>,
+  #[serde(alias = "Crypto")]
+  crypto: CryptoJson,
+  #[serde(default)]
+  id: String,
+  version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CryptoJson {
+  cipher: String,
+  cipherparams: CipherParams,
+  ciphertext: String,
+  kdf: String,
+  kdfparams: serde_json::Value,
+  mac: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CipherParams {
+  iv: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScryptParams {
+  dklen: usize,
+  n: u64,
+  r: u32,
+  p: u32,
+  salt: String,
+}
+
+#[derive(Deserialize)]
+struct Pbkdf2Params {
+  c: u32,
+  dklen: usize,
+  prf: String,
+  salt: String,
+}
+
+/// Decrypt a keystore v3 JSON document & return the private key. If the document states an
+/// address, it is verified against the decrypted key.
+pub fn decrypt(json: &str, password: impl AsRef<str>) -> Result<Vec<u8>> {
+  let keystore: KeystoreV3 = serde_json::from_str(json).map_err(invalid)?;
+  if keystore.version != 3 {
+    return Err(invalid(format!("unsupported version {}", keystore.version)));
+  }
+
+  let crypto = keystore.crypto;
+  if crypto.cipher != "aes-128-ctr" {
+    return Err(invalid(format!("unsupported cipher {}", crypto.cipher)));
+  }
+
+  let password = password.as_ref().as_bytes();
+  let mut derived = [0u8; 32];
+  match crypto.kdf.as_str() {
+    "scrypt" => {
+      let params: ScryptParams = serde_json::from_value(crypto.kdfparams).map_err(invalid)?;
+      if params.dklen != 32 {
+        return Err(invalid(format!("unsupported dklen {}", params.dklen)));
+      }
+      if !params.n.is_power_of_two() {
+        return Err(invalid("scrypt n must be a power of two"));
+      }
+      if params.n > MAX_SCRYPT_N || params.r > MAX_SCRYPT_R || params.p > MAX_SCRYPT_P {
+        return Err(invalid(format!(
+          "scrypt parameters exceed n = {}, r = {}, p = {}", MAX_SCRYPT_N, MAX_SCRYPT_R, MAX_SCRYPT_P,
+        )));
+      }
+      if params.r == 0 || params.p == 0 {
+        return Err(invalid("scrypt r & p must be positive"));
+      }
+      scrypt(password, &unhex(&params.salt)?, params.n as usize, params.r as usize, params.p as usize, &mut derived);
+    },
+    "pbkdf2" => {
+      let params: Pbkdf2Params = serde_json::from_value(crypto.kdfparams).map_err(invalid)?;
+      if params.dklen != 32 {
+        return Err(invalid(format!("unsupported dklen {}", params.dklen)));
+      }
+      if params.prf != "hmac-sha256" {
+        return Err(invalid(format!("unsupported prf {}", params.prf)));
+      }
+      if !(1..=MAX_PBKDF2_C).contains(&params.c) {
+        return Err(invalid(format!("pbkdf2 c must be between 1 and {}", MAX_PBKDF2_C)));
+      }
+      pbkdf2::pbkdf2::<Hmac<Sha256>>(password, &unhex(&params.salt)?, params.c, &mut derived)
+        .map_err(invalid)?;
+    },
+    kdf => return Err(invalid(format!("unsupported kdf {}", kdf))),
+  }
+
+  let mut ciphertext = unhex(&crypto.ciphertext)?;
+  let mac = keccak256([&derived[16..32], &ciphertext].concat());
+  if unhex(&crypto.mac)? != mac {
+    return Err(Error::WrongPassword);
+  }
+
+  let iv: [u8; 16] = unhex(&crypto.cipherparams.iv)?
+    .try_into()
+    .map_err(|_| invalid("iv must be 16 bytes"))?;
+  Aes128Ctr::new(derived[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+  let privkey = ciphertext;
+
+  if let Some(expected) = keystore.address {
+    let actual = hex::encode(address(&privkey)?);
+    if expected.trim_start_matches("0x").to_lowercase() != actual {
+      return Err(Error::InvalidValue(format!(
+        "Address mismatch: keystore states 0x{} but the key belongs to 0x{}",
+        expected.trim_start_matches("0x"),
+        actual,
+      )));
+    }
+  }
+
+  Ok(privkey)
+}
+
+/// Encrypt a secp256k1 private key into a keystore v3 JSON document using scrypt with `N = 2^log_n`,
+/// `r = 8`, `p = 1` & AES-128-CTR.
+pub fn encrypt(privkey: &[u8], password: impl AsRef<str>, log_n: u8) -> Result<String> {
+  let address = address(privkey)?;
+
+  let mut salt = [0u8; 32];
+  let mut iv = [0u8; 16];
+  let mut id = [0u8; 16];
+  OsRng.fill_bytes(&mut salt);
+  OsRng.fill_bytes(&mut iv);
+  OsRng.fill_bytes(&mut id);
+
+  let params = scrypt::Params::new(log_n, 8, 1, 32)
+    .map_err(|err| Error::InvalidValue(format!("Invalid scrypt parameters: {}", err)))?;
+  let mut derived = [0u8; 32];
+  scrypt::scrypt(password.as_ref().as_bytes(), &salt, &params, &mut derived)
+    .map_err(|err| Error::Other(format!("Failed to derive key: {}", err)))?;
+
+  let mut ciphertext = privkey.to_vec();
+  Aes128Ctr::new(derived[..16].into(), &iv.into()).apply_keystream(&mut ciphertext);
+  let mac = keccak256([&derived[16..32], &ciphertext].concat());
+
+  let keystore = KeystoreV3 {
+    address: Some(hex::encode(address)),
+    crypto: CryptoJson {
+      cipher: "aes-128-ctr".to_string(),
+      cipherparams: CipherParams { iv: hex::encode(iv) },
+      ciphertext: hex::encode(ciphertext),
+      kdf: "scrypt".to_string(),
+      kdfparams: serde_json::to_value(ScryptParams {
+        dklen: 32,
+        n: 1 << log_n,
+        r: 8,
+        p: 1,
+        salt: hex::encode(salt),
+      }).unwrap(),
+      mac: hex::encode(mac),
+    },
+    id: uuid_v4(id),
+    version: 3,
+  };
+  Ok(serde_json::to_string(&keystore).unwrap())
+}
+
+/// scrypt as implemented by geth. Unlike the `scrypt` crate, it does not require `n < 2^(16 r)`, which
+/// e.g. the `r = 1` of the Web3 Secret Storage test vectors violates. `n` must be a power of two.
+fn scrypt(password: &[u8], salt: &[u8], n: usize, r: usize, p: usize, output: &mut [u8]) {
+  let len = 128 * r;
+  let mut b = vec![0u8; p * len];
+  pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, 1, &mut b);
+
+  let mut v = vec![0u8; n * len];
+  let mut t = vec![0u8; len];
+  for chunk in b.chunks_mut(len) {
+    ro_mix(chunk, &mut v, &mut t, n);
+  }
+  pbkdf2::pbkdf2_hmac::<Sha256>(password, &b, 1, output);
+}
+
+fn ro_mix(b: &mut [u8], v: &mut [u8], t: &mut [u8], n: usize) {
+  let len = b.len();
+  for chunk in v.chunks_mut(len) {
+    chunk.copy_from_slice(b);
+    block_mix(chunk, b);
+  }
+  for _ in 0..n {
+    // integerify, reduced mod n by masking as n is a power of two
+    let j = u32::from_le_bytes(b[len - 64..len - 60].try_into().unwrap()) as usize & (n - 1);
+    for ((t, b), v) in t.iter_mut().zip(b.iter()).zip(&v[j * len..(j + 1) * len]) {
+      *t = b ^ v;
+    }
+    block_mix(t, b);
+  }
+}
+
+fn block_mix(input: &[u8], output: &mut [u8]) {
+  use salsa20::cipher::typenum::U4;
+  use salsa20::cipher::StreamCipherCore;
+  use salsa20::SalsaCore;
+
+  let mut x: [u8; 64] = input[input.len() - 64..].try_into().unwrap();
+  for (i, chunk) in input.chunks(64).enumerate() {
+    let mut state = [0u32; 16];
+    for (word, (x, chunk)) in state.iter_mut().zip(x.chunks_exact(4).zip(chunk.chunks_exact(4))) {
+      *word = u32::from_le_bytes(x.try_into().unwrap()) ^ u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    // Salsa20/8 core
+    SalsaCore::<U4>::from_raw_state(state).write_keystream_block((&mut x).into());
+
+    // even blocks go to the first half of the output, odd blocks to the second
+    let pos = (i / 2) * 64 + if i % 2 == 0 { 0 } else { input.len() / 2 };
+    output[pos..pos + 64].copy_from_slice(&x);
+  }
+}
+
+fn uuid_v4(mut bytes: [u8; 16]) -> String {
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex = hex::encode(bytes);
+  format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn unhex(value: &str) -> Result<Vec<u8>> {
+  hex::decode(value.trim_start_matches("0x")).map_err(invalid)
+}
+
+fn invalid(err: impl ToString) -> Error {
+  Error::InvalidValue(format!("Invalid eth-keystore: {}", err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // test vectors of the Web3 Secret Storage definition, as used by geth
+  const PASSWORD: &str = "testpassword";
+  const PRIVKEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+  const PBKDF2: &str = r#"{
+    "crypto": {
+      "cipher": "aes-128-ctr",
+      "cipherparams": {"iv": "6087dab2f9fdbbfaddc31a909735c1e6"},
+      "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+      "kdf": "pbkdf2",
+      "kdfparams": {
+        "c": 262144,
+        "dklen": 32,
+        "prf": "hmac-sha256",
+        "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+      },
+      "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+    },
+    "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+    "version": 3
+  }"#;
+
+  const SCRYPT: &str = r#"{
+    "crypto": {
+      "cipher": "aes-128-ctr",
+      "cipherparams": {"iv": "83dbcc02d8ccb40e466191a123791e0e"},
+      "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+      "kdf": "scrypt",
+      "kdfparams": {
+        "dklen": 32,
+        "n": 262144,
+        "p": 8,
+        "r": 1,
+        "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+      },
+      "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+    },
+    "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+    "version": 3
+  }"#;
+
+  fn with_kdfparams(json: &str, name: &str, value: serde_json::Value) -> String {
+    let mut json: serde_json::Value = serde_json::from_str(json).unwrap();
+    json["crypto"]["kdfparams"][name] = value;
+    json.to_string()
+  }
+
+  #[test]
+  fn decrypts_pbkdf2_vector() {
+    assert_eq!(hex::encode(decrypt(PBKDF2, PASSWORD).unwrap()), PRIVKEY);
+    assert!(matches!(decrypt(PBKDF2, "wrongpassword"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn decrypts_scrypt_vector() {
+    assert_eq!(hex::encode(decrypt(SCRYPT, PASSWORD).unwrap()), PRIVKEY);
+  }
+
+  #[test]
+  fn verifies_address() {
+    let with_address = |address: &str| {
+      let mut json: serde_json::Value = serde_json::from_str(PBKDF2).unwrap();
+      json["address"] = address.into();
+      json.to_string()
+    };
+    for address in ["008aeeda4d805471df9b2a5b0f38a0c3bcba786b", "0x008AEeda4D805471dF9b2A5B0f38A0C3bCBA786b"] {
+      assert_eq!(hex::encode(decrypt(&with_address(address), PASSWORD).unwrap()), PRIVKEY);
+    }
+    let mismatch = with_address("0x2c7536e3605d9c16a7a3d7b1898e529396a65c23");
+    assert!(matches!(decrypt(&mismatch, PASSWORD), Err(Error::InvalidValue(message)) if message.starts_with("Address mismatch")));
+  }
+
+  #[test]
+  fn round_trips() {
+    let privkey = hex::decode(PRIVKEY).unwrap();
+    let json = encrypt(&privkey, PASSWORD, LIGHT_LOG_N).unwrap();
+    assert_eq!(decrypt(&json, PASSWORD).unwrap(), privkey);
+  }
+
+  #[test]
+  fn rejects_excessive_costs() {
+    let cases = [
+      with_kdfparams(SCRYPT, "n", (1u64 << 40).into()),
+      with_kdfparams(SCRYPT, "n", 262143.into()),
+      with_kdfparams(SCRYPT, "r", 1024.into()),
+      with_kdfparams(SCRYPT, "p", 1024.into()),
+      with_kdfparams(SCRYPT, "dklen", 64.into()),
+      with_kdfparams(PBKDF2, "c", u32::MAX.into()),
+      with_kdfparams(PBKDF2, "c", 0.into()),
+    ];
+    for json in cases {
+      assert!(matches!(decrypt(&json, PASSWORD), Err(Error::InvalidValue(_))), "{}", json);
+    }
+  }
+}
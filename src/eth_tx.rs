@@ -0,0 +1,436 @@
+//! Offline signing of Ethereum legacy (EIP-155), EIP-2930 & EIP-1559 transactions.
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::eth::{keccak256, sign_hash};
+use crate::rlp::Rlp;
+
+/// Envelope type of a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxType {
+  Legacy,
+  /// EIP-2930 transaction with an access list.
+  AccessList,
+  /// EIP-1559 transaction with dynamic fees.
+  DynamicFee,
+}
+
+/// An unsigned Ethereum transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+  pub tx_type: TxType,
+  /// Chain ID. Only legacy transactions may omit it, in which case they can only be signed without
+  /// EIP-155 replay protection by [`Transaction::sign_without_replay_protection`].
+  pub chain_id: Option<u64>,
+  pub nonce: u64,
+  /// Gas price of legacy & EIP-2930 transactions.
+  pub gas_price: u128,
+  /// Priority fee of EIP-1559 transactions.
+  pub max_priority_fee_per_gas: u128,
+  /// Max fee of EIP-1559 transactions.
+  pub max_fee_per_gas: u128,
+  pub gas_limit: u64,
+  /// Recipient, or `None` to deploy a contract.
+  pub to: Option<[u8; 20]>,
+  pub value: u128,
+  pub data: Vec<u8>,
+  pub access_list: Vec<AccessListItem>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessListItem {
+  pub address: [u8; 20],
+  pub storage_keys: Vec<[u8; 32]>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TxJson {
+  #[serde(rename = "type")]
+  tx_type: Option<Quantity>,
+  chain_id: Option<Quantity>,
+  nonce: Quantity,
+  gas_price: Option<Quantity>,
+  max_priority_fee_per_gas: Option<Quantity>,
+  max_fee_per_gas: Option<Quantity>,
+  #[serde(alias = "gasLimit")]
+  gas: Quantity,
+  to: Option<String>,
+  value: Option<Quantity>,
+  #[serde(alias = "input")]
+  data: Option<String>,
+  access_list: Option<Vec<AccessListJson>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessListJson {
+  address: String,
+  storage_keys: Vec<String>,
+}
+
+/// Integer given either as JSON number, hex string or decimal string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+  Number(u64),
+  String(String),
+}
+
+impl Transaction {
+  /// Parse an unsigned transaction given either as JSON object in the format of
+  /// `eth_signTransaction`, or as hex encoded RLP.
+  pub fn parse(input: &str) -> Result<Self> {
+    let input = input.trim();
+    if input.starts_with('{') {
+      Self::from_json(input)
+    } else {
+      let bytes = hex::decode(input.trim_start_matches("0x")).map_err(invalid)?;
+      Self::from_rlp(&bytes)
+    }
+  }
+
+  /// Parse an unsigned transaction from a JSON object in the format of `eth_signTransaction`. The
+  /// type is inferred from the fields present unless given explicitly.
+  pub fn from_json(json: &str) -> Result<Self> {
+    let tx: TxJson = serde_json::from_str(json).map_err(invalid)?;
+
+    let tx_type = match &tx.tx_type {
+      Some(tx_type) => match tx_type.to_u128()? {
+        0 => TxType::Legacy,
+        1 => TxType::AccessList,
+        2 => TxType::DynamicFee,
+        other => return Err(invalid(format!("unsupported type {}", other))),
+      },
+      None if tx.max_fee_per_gas.is_some() => TxType::DynamicFee,
+      None if tx.access_list.is_some() => TxType::AccessList,
+      None => TxType::Legacy,
+    };
+
+    let required = |field: &Option<Quantity>, name: &str| match field {
+      Some(value) => value.to_u128(),
+      None => Err(invalid(format!("missing field {}", name))),
+    };
+    let (gas_price, max_priority_fee_per_gas, max_fee_per_gas) = match tx_type {
+      TxType::Legacy | TxType::AccessList => (required(&tx.gas_price, "gasPrice")?, 0, 0),
+      TxType::DynamicFee => (
+        0,
+        required(&tx.max_priority_fee_per_gas, "maxPriorityFeePerGas")?,
+        required(&tx.max_fee_per_gas, "maxFeePerGas")?,
+      ),
+    };
+
+    let chain_id = tx.chain_id.as_ref().map(|id| id.to_u64()).transpose()?;
+    if chain_id.is_none() && tx_type != TxType::Legacy {
+      return Err(invalid("missing field chainId"));
+    }
+
+    let access_list = tx.access_list.unwrap_or_default()
+      .into_iter()
+      .map(|item| Ok(AccessListItem {
+        address: unhex_fixed(&item.address)?,
+        storage_keys: item.storage_keys.iter().map(|key| unhex_fixed(key)).collect::<Result<_>>()?,
+      }))
+      .collect::<Result<_>>()?;
+
+    Ok(Self {
+      tx_type,
+      chain_id,
+      nonce: tx.nonce.to_u64()?,
+      gas_price,
+      max_priority_fee_per_gas,
+      max_fee_per_gas,
+      gas_limit: tx.gas.to_u64()?,
+      to: tx.to.as_deref().map(unhex_fixed).transpose()?,
+      value: tx.value.map(|value| value.to_u128()).transpose()?.unwrap_or_default(),
+      data: tx.data.as_deref().map(unhex).transpose()?.unwrap_or_default(),
+      access_list,
+    })
+  }
+
+  /// Parse the RLP encoding of an unsigned transaction as produced by [`Transaction::signing_payload`].
+  pub fn from_rlp(bytes: &[u8]) -> Result<Self> {
+    let (tx_type, payload) = match bytes.first() {
+      Some(1) => (TxType::AccessList, &bytes[1..]),
+      Some(2) => (TxType::DynamicFee, &bytes[1..]),
+      Some(0xc0..) => (TxType::Legacy, bytes),
+      Some(other) => return Err(invalid(format!("unsupported type {}", other))),
+      None => return Err(invalid("empty input")),
+    };
+    Self::decode_fields(tx_type, payload).map_err(invalid)
+  }
+
+  fn decode_fields(tx_type: TxType, payload: &[u8]) -> std::result::Result<Self, String> {
+    let rlp = Rlp::decode(payload)?;
+    let fields = rlp.as_list()?;
+    let expected = match tx_type {
+      TxType::Legacy if fields.len() == 6 => 6,
+      TxType::Legacy => 9,
+      TxType::AccessList => 8,
+      TxType::DynamicFee => 9,
+    };
+    if fields.len() != expected {
+      return Err(format!("expected {} fields, found {}", expected, fields.len()));
+    }
+
+    let mut tx = Self {
+      tx_type,
+      chain_id: None,
+      nonce: 0,
+      gas_price: 0,
+      max_priority_fee_per_gas: 0,
+      max_fee_per_gas: 0,
+      gas_limit: 0,
+      to: None,
+      value: 0,
+      data: vec![],
+      access_list: vec![],
+    };
+
+    let mut fields = fields.iter();
+    let mut next = || fields.next().unwrap();
+    if tx_type != TxType::Legacy {
+      tx.chain_id = Some(to_u64(next().as_uint()?)?);
+    }
+    tx.nonce = to_u64(next().as_uint()?)?;
+    if tx_type == TxType::DynamicFee {
+      tx.max_priority_fee_per_gas = next().as_uint()?;
+      tx.max_fee_per_gas = next().as_uint()?;
+    } else {
+      tx.gas_price = next().as_uint()?;
+    }
+    tx.gas_limit = to_u64(next().as_uint()?)?;
+    tx.to = match next().as_bytes()? {
+      [] => None,
+      to => Some(to.try_into().map_err(|_| "to must be 20 bytes")?),
+    };
+    tx.value = next().as_uint()?;
+    tx.data = next().as_bytes()?.to_vec();
+
+    if tx_type == TxType::Legacy {
+      if expected == 9 {
+        // EIP-155 signing payload: chain ID followed by two empty fields
+        tx.chain_id = Some(to_u64(next().as_uint()?)?);
+        if next().as_uint()? != 0 || next().as_uint()? != 0 {
+          return Err("transaction is already signed".to_string());
+        }
+      }
+    } else {
+      for item in next().as_list()? {
+        let [address, storage_keys] = item.as_list()? else {
+          return Err("access list items must have 2 fields".to_string());
+        };
+        tx.access_list.push(AccessListItem {
+          address: address.as_bytes()?.try_into().map_err(|_| "address must be 20 bytes")?,
+          storage_keys: storage_keys.as_list()?.iter()
+            .map(|key| key.as_bytes()?.try_into().map_err(|_| "storage key must be 32 bytes".to_string()))
+            .collect::<std::result::Result<_, String>>()?,
+        });
+      }
+    }
+
+    Ok(tx)
+  }
+
+  /// Encoding of the transaction whose hash is signed.
+  pub fn signing_payload(&self) -> Vec<u8> {
+    let mut fields = self.common_fields();
+    if self.tx_type == TxType::Legacy {
+      if let Some(chain_id) = self.chain_id {
+        fields.extend([Rlp::uint(chain_id as u128), Rlp::uint(0), Rlp::uint(0)]);
+      }
+    }
+    self.envelope(fields)
+  }
+
+  pub fn signing_hash(&self) -> [u8; 32] {
+    keccak256(self.signing_payload())
+  }
+
+  /// Sign the transaction with a secp256k1 private key & return the signed raw transaction, ready to
+  /// be submitted with `eth_sendRawTransaction`. Fails for legacy transactions without chain ID, as
+  /// they could be replayed on other chains.
+  pub fn sign(&self, privkey: &[u8]) -> Result<Vec<u8>> {
+    if self.chain_id.is_none() {
+      return Err(invalid("missing field chainId, without which the transaction could be replayed on other chains"));
+    }
+    self.sign_without_replay_protection(privkey)
+  }
+
+  /// Like [`Transaction::sign`], but signs legacy transactions without chain ID as before EIP-155.
+  pub fn sign_without_replay_protection(&self, privkey: &[u8]) -> Result<Vec<u8>> {
+    let sig = sign_hash(privkey, &self.signing_hash())?;
+    let v = match (self.tx_type, self.chain_id) {
+      (TxType::Legacy, Some(chain_id)) => chain_id as u128 * 2 + 35 + sig.y_parity as u128,
+      (TxType::Legacy, None) => 27 + sig.y_parity as u128,
+      _ => sig.y_parity as u128,
+    };
+
+    let mut fields = self.common_fields();
+    fields.extend([Rlp::uint(v), uint_bytes(&sig.r), uint_bytes(&sig.s)]);
+    Ok(self.envelope(fields))
+  }
+
+  fn common_fields(&self) -> Vec<Rlp> {
+    let mut fields = vec![];
+    if self.tx_type != TxType::Legacy {
+      fields.push(Rlp::uint(self.chain_id.unwrap_or_default() as u128));
+    }
+    fields.push(Rlp::uint(self.nonce as u128));
+    match self.tx_type {
+      TxType::DynamicFee => fields.extend([
+        Rlp::uint(self.max_priority_fee_per_gas),
+        Rlp::uint(self.max_fee_per_gas),
+      ]),
+      _ => fields.push(Rlp::uint(self.gas_price)),
+    }
+    fields.extend([
+      Rlp::uint(self.gas_limit as u128),
+      Rlp::Bytes(self.to.map(|to| to.to_vec()).unwrap_or_default()),
+      Rlp::uint(self.value),
+      Rlp::Bytes(self.data.clone()),
+    ]);
+    if self.tx_type != TxType::Legacy {
+      fields.push(Rlp::List(self.access_list.iter()
+        .map(|item| Rlp::List(vec![
+          Rlp::Bytes(item.address.to_vec()),
+          Rlp::List(item.storage_keys.iter().map(|key| Rlp::Bytes(key.to_vec())).collect()),
+        ]))
+        .collect()));
+    }
+    fields
+  }
+
+  fn envelope(&self, fields: Vec<Rlp>) -> Vec<u8> {
+    let mut out = match self.tx_type {
+      TxType::Legacy => vec![],
+      TxType::AccessList => vec![1],
+      TxType::DynamicFee => vec![2],
+    };
+    out.extend(Rlp::List(fields).encode());
+    out
+  }
+}
+
+impl Quantity {
+  fn to_u128(&self) -> Result<u128> {
+    match self {
+      Quantity::Number(value) => Ok(*value as u128),
+      Quantity::String(value) => match value.strip_prefix("0x") {
+        Some("") => Ok(0),
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => value.parse(),
+      }.map_err(|_| invalid(format!("invalid quantity {}", value))),
+    }
+  }
+
+  fn to_u64(&self) -> Result<u64> {
+    to_u64(self.to_u128()?).map_err(invalid)
+  }
+}
+
+fn to_u64(value: u128) -> std::result::Result<u64, String> {
+  value.try_into().map_err(|_| format!("{} exceeds 64 bits", value))
+}
+
+/// Big-endian integer bytes as found in signatures, stripped of leading zeros.
+fn uint_bytes(bytes: &[u8]) -> Rlp {
+  let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+  Rlp::Bytes(bytes[start..].to_vec())
+}
+
+fn unhex(value: &str) -> Result<Vec<u8>> {
+  hex::decode(value.trim_start_matches("0x")).map_err(invalid)
+}
+
+fn unhex_fixed<const N: usize>(value: &str) -> Result<[u8; N]> {
+  unhex(value)?
+    .try_into()
+    .map_err(|_| invalid(format!("{} must be {} bytes", value, N)))
+}
+
+fn invalid(err: impl ToString) -> Error {
+  Error::InvalidValue(format!("Invalid transaction: {}", err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // example of EIP-155
+  const EIP155_PRIVKEY: &str = "4646464646464646464646464646464646464646464646464646464646464646";
+  const EIP155_SIGNING_PAYLOAD: &str = "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080";
+  const EIP155_SIGNED: &str = "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+
+  fn eip155_tx() -> Transaction {
+    Transaction::from_json(r#"{
+      "chainId": 1,
+      "nonce": 9,
+      "gasPrice": "20000000000",
+      "gas": 21000,
+      "to": "0x3535353535353535353535353535353535353535",
+      "value": "1000000000000000000"
+    }"#).unwrap()
+  }
+
+  #[test]
+  fn signs_eip155_example() {
+    let tx = eip155_tx();
+    assert_eq!(tx.tx_type, TxType::Legacy);
+    assert_eq!(hex::encode(tx.signing_payload()), EIP155_SIGNING_PAYLOAD);
+    assert_eq!(
+      hex::encode(tx.signing_hash()),
+      "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53",
+    );
+    assert_eq!(hex::encode(tx.sign(&hex::decode(EIP155_PRIVKEY).unwrap()).unwrap()), EIP155_SIGNED);
+  }
+
+  #[test]
+  fn parses_signing_payload() {
+    assert_eq!(Transaction::parse(EIP155_SIGNING_PAYLOAD).unwrap(), eip155_tx());
+  }
+
+  #[test]
+  fn refuses_signing_without_chain_id() {
+    let privkey = hex::decode(EIP155_PRIVKEY).unwrap();
+    let tx = Transaction { chain_id: None, ..eip155_tx() };
+    assert!(tx.sign(&privkey).is_err());
+
+    // pre-EIP-155 signatures use v = 27 or 28
+    let raw = tx.sign_without_replay_protection(&privkey).unwrap();
+    let rlp = Rlp::decode(&raw).unwrap();
+    assert!(matches!(rlp.as_list().unwrap()[6].as_uint().unwrap(), 27 | 28));
+  }
+
+  #[test]
+  fn round_trips_typed_transactions() {
+    let tx = Transaction::from_json(r#"{
+      "chainId": "0x5",
+      "nonce": "0x1",
+      "maxPriorityFeePerGas": "0x3b9aca00",
+      "maxFeePerGas": "0x77359400",
+      "gas": 30000,
+      "to": "0x3535353535353535353535353535353535353535",
+      "data": "0xdeadbeef",
+      "accessList": [{
+        "address": "0x3535353535353535353535353535353535353535",
+        "storageKeys": ["0x0000000000000000000000000000000000000000000000000000000000000001"]
+      }]
+    }"#).unwrap();
+    assert_eq!(tx.tx_type, TxType::DynamicFee);
+    let payload = tx.signing_payload();
+    assert_eq!(payload[0], 2);
+    assert_eq!(Transaction::from_rlp(&payload).unwrap(), tx);
+
+    let access_list = Transaction { tx_type: TxType::AccessList, gas_price: 1, ..tx };
+    let payload = access_list.signing_payload();
+    assert_eq!(payload[0], 1);
+    assert_eq!(Transaction::from_rlp(&payload).unwrap().access_list, access_list.access_list);
+  }
+
+  #[test]
+  fn requires_chain_id_of_typed_transactions() {
+    let json = r#"{"nonce": 0, "maxPriorityFeePerGas": 1, "maxFeePerGas": 2, "gas": 21000}"#;
+    assert!(Transaction::from_json(json).is_err());
+  }
+}
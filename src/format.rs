@@ -0,0 +1,845 @@
+//! Parser & serializer for the on-disk keystore format. Parsing validates every length & version
+//! byte and never panics, no matter how malformed the input.
+//!
+//! v1: `[1u8] ++ salt(16) ++ nonce(12) ++ ciphertext`
+//!
+//! v2: `[2u8] ++ u16be(len) ++ header(len) ++ ciphertext`, where the header is a sequence of
+//! tag-length-value records `tag(1) ++ u16be(len) ++ value(len)`.
+//!
+//! v3: `[3u8] ++ u16be(len) ++ header(len) ++ u16be(len) ++ slots(len) ++ ciphertext`. The secret is
+//! encrypted under a random data key, which each slot wraps for another credential. Slots are records
+//! like those of the header, but not authenticated alongside the ciphertext, so they can be added &
+//! removed without re-encrypting the secret.
+//!
+//! The master key file of a keyring is `[1u8] ++ slots`, holding password slots which wrap the master key
+//! of the keyring instead of a data key.
+use std::fmt;
+
+use crate::date;
+use crate::encoding::Encoding;
+use crate::error::{Error, Result as KayringResult};
+use crate::kdf::Kdf;
+
+const TAG_KDF: u8 = 1;
+const TAG_CIPHER: u8 = 2;
+const TAG_CREATED: u8 = 3;
+const TAG_SALT: u8 = 4;
+const TAG_NONCE: u8 = 5;
+const TAG_KIND: u8 = 6;
+const TAG_NOT_AFTER: u8 = 7;
+const TAG_MAX_READS: u8 = 8;
+const TAG_READS: u8 = 9;
+const TAG_POLICY_FLAGS: u8 = 10;
+const TAG_ENCODING: u8 = 11;
+
+const SLOT_PASSWORD: u8 = 1;
+const SLOT_X25519: u8 = 2;
+const SLOT_MASTER: u8 = 3;
+const SLOT_KEYFILE: u8 = 4;
+
+const CIPHER_AES256GCM: u8 = 1;
+
+const KIND_RAW: u8 = 0;
+const KIND_BIP39_SEED: u8 = 1;
+
+const ENCODING_UTF8: u8 = 1;
+const ENCODING_BASE64: u8 = 2;
+const ENCODING_BASE58: u8 = 3;
+const ENCODING_FILE: u8 = 4;
+
+const FLAG_CONFIRM: u8 = 1;
+const FLAG_REFUSE_SILENT: u8 = 2;
+
+pub(crate) const SALT_LEN: usize = 16;
+pub(crate) const NONCE_LEN: usize = 12;
+/// Length of the AES-GCM authentication tag appended to every ciphertext.
+const TAG_LEN: usize = 16;
+/// Length of a data key wrapped with AES-GCM.
+pub(crate) const WRAPPED_KEY_LEN: usize = 32 + TAG_LEN;
+
+/// Describes what is wrong with a malformed keystore file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FormatError {
+  Empty,
+  UnknownVersion(u8),
+  /// The file ends before the named part is complete.
+  Truncated(&'static str),
+  UnknownField(u8),
+  UnknownSlot(u8),
+  DuplicateField(&'static str),
+  MissingField(&'static str),
+  /// The named field is present but its contents are malformed.
+  InvalidField(&'static str, String),
+}
+
+impl fmt::Display for FormatError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FormatError::Empty => write!(f, "file is empty"),
+      FormatError::UnknownVersion(ver) => write!(f, "unknown file version {}", ver),
+      FormatError::Truncated(what) => write!(f, "truncated {}", what),
+      FormatError::UnknownField(tag) => write!(f, "unknown header field {}", tag),
+      FormatError::UnknownSlot(tag) => write!(f, "unknown key slot {}", tag),
+      FormatError::DuplicateField(field) => write!(f, "duplicate header field {}", field),
+      FormatError::MissingField(field) => write!(f, "missing header field {}", field),
+      FormatError::InvalidField(field, reason) => write!(f, "invalid {}: {}", field, reason),
+    }
+  }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Symmetric cipher used to encrypt the keystore contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherKind {
+  Aes256Gcm,
+}
+
+/// What kind of secret a keystore holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SecretKind {
+  /// A raw secret, usually a private key.
+  #[default]
+  Raw,
+  /// A 64 byte BIP-39 seed from which BIP-32 child keys are derived.
+  Bip39Seed,
+}
+
+/// Information about the secret of a keystore. Authenticated alongside the secret, but not
+/// encrypted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+  pub kind: SecretKind,
+  /// Representation of the secret as given by the user.
+  pub encoding: Encoding,
+  pub policy: Policy,
+}
+
+/// Restrictions on reading the secret of a keystore. The not-after date & read limit are enforced by
+/// [`crate::Keyring::get`], the interactive restrictions by the CLI.
+///
+/// Policies guard against accidental use of stale keys, not against anyone knowing the password, who
+/// may always decrypt the keystore by other means.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+  /// Time in seconds since the unix epoch after which the secret may no longer be read.
+  pub not_after: Option<u64>,
+  /// Maximum number of times the secret may be read.
+  pub max_reads: Option<u32>,
+  /// Number of times the secret has been read. Only tracked if `max_reads` is set.
+  pub reads: u32,
+  /// Require interactive confirmation before revealing the secret.
+  pub confirm: bool,
+  /// Refuse to reveal the secret non-interactively, e.g. in `--silent` mode.
+  pub refuse_silent: bool,
+}
+
+impl Policy {
+  /// Whether the policy imposes no restrictions at all.
+  pub fn is_empty(&self) -> bool {
+    *self == Self::default()
+  }
+
+  /// Check the not-after date & read limit. `name` names the keystore in the error.
+  pub fn check(&self, name: &str) -> KayringResult<()> {
+    if let Some(not_after) = self.not_after {
+      if date::now() > not_after {
+        return Err(Error::Denied(format!("{} expired on {}", name, date::format(not_after))));
+      }
+    }
+    if let Some(max_reads) = self.max_reads {
+      if self.reads >= max_reads {
+        return Err(Error::Denied(format!("{} has been read the maximum of {} times", name, max_reads)));
+      }
+    }
+    Ok(())
+  }
+
+  /// Whether the `other` policy permits anything this policy refuses: a later or no not-after date, more
+  /// remaining reads, or lifting an interactive restriction.
+  pub fn is_relaxed_by(&self, other: &Policy) -> bool {
+    let remaining = |policy: &Policy| policy.max_reads.map(|max_reads| max_reads.saturating_sub(policy.reads));
+    let extends = |current: Option<u64>, new: Option<u64>| match (current, new) {
+      (Some(current), Some(new)) => new > current,
+      (Some(_), None) => true,
+      (None, _) => false,
+    };
+    extends(self.not_after, other.not_after)
+      || extends(remaining(self).map(u64::from), remaining(other).map(u64::from))
+      || (self.confirm && !other.confirm)
+      || (self.refuse_silent && !other.refuse_silent)
+  }
+
+  /// Parse a not-after date given as `YYYY-MM-DD` (end of that day in UTC), `YYYY-MM-DDTHH:MM:SSZ` or
+  /// seconds since the unix epoch.
+  pub fn parse_date(value: &str) -> KayringResult<u64> {
+    date::parse(value)
+      .ok_or_else(|| Error::InvalidValue(format!("Invalid date {}. Expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or a unix timestamp", value)))
+  }
+}
+
+impl fmt::Display for Policy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let yes_no = |flag| if flag { "yes" } else { "no" };
+    match self.not_after {
+      Some(not_after) => writeln!(f, "not after: {}", date::format(not_after))?,
+      None => writeln!(f, "not after: never")?,
+    }
+    match self.max_reads {
+      Some(max_reads) => writeln!(f, "reads: {} of {}", self.reads, max_reads)?,
+      None => writeln!(f, "reads: unlimited")?,
+    }
+    writeln!(f, "confirm: {}", yes_no(self.confirm))?;
+    write!(f, "refuse silent: {}", yes_no(self.refuse_silent))
+  }
+}
+
+/// Header of a v2 keystore. The serialized header is authenticated as associated data of the
+/// ciphertext.
+#[derive(Clone, Debug)]
+pub struct Header {
+  pub kdf: Kdf,
+  pub cipher: CipherKind,
+  /// Creation time in seconds since the unix epoch.
+  pub created: u64,
+  pub salt: Vec<u8>,
+  pub nonce: [u8; 12],
+  pub metadata: Metadata,
+}
+
+/// Header of a v3 keystore, whose secret is encrypted under a data key wrapped by its [`Slot`]s.
+#[derive(Clone, Debug)]
+pub struct EnvelopeHeader {
+  pub cipher: CipherKind,
+  /// Creation time in seconds since the unix epoch.
+  pub created: u64,
+  pub nonce: [u8; 12],
+  pub metadata: Metadata,
+}
+
+/// Data key of a v3 keystore wrapped for a single credential.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Slot {
+  /// Wrapped under a key derived from a password.
+  Password {
+    kdf: Kdf,
+    salt: Vec<u8>,
+    nonce: [u8; 12],
+    wrapped_key: Vec<u8>,
+  },
+  /// Wrapped for the holder of an X25519 identity, see [`crate::recipient`].
+  X25519 {
+    recipient: [u8; 32],
+    /// Public key of the ephemeral key pair the wrapping key was agreed with.
+    ephemeral: [u8; 32],
+    wrapped_key: Vec<u8>,
+  },
+  /// Wrapped under the master key of the keyring, see [`crate::Keyring::init_master`].
+  Master {
+    nonce: [u8; 12],
+    wrapped_key: Vec<u8>,
+  },
+  /// Wrapped under a key derived from the contents of a keyfile with HKDF-SHA256.
+  Keyfile {
+    salt: Vec<u8>,
+    nonce: [u8; 12],
+    wrapped_key: Vec<u8>,
+  },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Format {
+  V1 {
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+  },
+  V2 {
+    header: Header,
+    /// Header exactly as read from disk, as it is authenticated alongside the ciphertext.
+    header_bytes: Vec<u8>,
+    ciphertext: Vec<u8>,
+  },
+  V3 {
+    header: EnvelopeHeader,
+    /// Header exactly as read from disk, as it is authenticated alongside the ciphertext.
+    header_bytes: Vec<u8>,
+    slots: Vec<Slot>,
+    ciphertext: Vec<u8>,
+  },
+}
+
+impl Format {
+  pub(crate) fn parse(contents: &[u8]) -> Result<Self, FormatError> {
+    if contents.is_empty() {
+      return Err(FormatError::Empty);
+    }
+    let mut reader = Reader(contents);
+    match reader.u8("version")? {
+      1 => {
+        let salt = reader.take(SALT_LEN, "salt")?.try_into().unwrap();
+        let nonce = reader.take(NONCE_LEN, "nonce")?.try_into().unwrap();
+        Ok(Format::V1 { salt, nonce, ciphertext: ciphertext(reader.0)? })
+      },
+      2 => {
+        let len = reader.u16("header length")?;
+        let header_bytes = reader.take(len as usize, "header")?;
+        Ok(Format::V2 {
+          header: Header::parse(header_bytes)?,
+          header_bytes: header_bytes.to_vec(),
+          ciphertext: ciphertext(reader.0)?,
+        })
+      },
+      3 => {
+        let len = reader.u16("header length")?;
+        let header_bytes = reader.take(len as usize, "header")?;
+        let len = reader.u16("slots length")?;
+        let slots = parse_slots(reader.take(len as usize, "slots")?)?;
+        Ok(Format::V3 {
+          header: EnvelopeHeader::parse(header_bytes)?,
+          header_bytes: header_bytes.to_vec(),
+          slots,
+          ciphertext: ciphertext(reader.0)?,
+        })
+      },
+      ver => Err(FormatError::UnknownVersion(ver)),
+    }
+  }
+
+  pub(crate) fn to_bytes(&self) -> Vec<u8> {
+    match self {
+      Format::V1 { salt, nonce, ciphertext } => [
+        vec![1u8], // file version 1
+        salt.to_vec(),
+        nonce.to_vec(),
+        ciphertext.clone(),
+      ].concat(),
+      Format::V2 { header_bytes, ciphertext, .. } => [
+        vec![2u8], // file version 2
+        (header_bytes.len() as u16).to_be_bytes().to_vec(),
+        header_bytes.clone(),
+        ciphertext.clone(),
+      ].concat(),
+      Format::V3 { header_bytes, slots, ciphertext, .. } => {
+        let slots = write_slots(slots);
+        [
+          vec![3u8], // file version 3
+          (header_bytes.len() as u16).to_be_bytes().to_vec(),
+          header_bytes.clone(),
+          (slots.len() as u16).to_be_bytes().to_vec(),
+          slots,
+          ciphertext.clone(),
+        ].concat()
+      },
+    }
+  }
+
+  pub(crate) fn version(&self) -> u8 {
+    match self {
+      Format::V1 { .. } => 1,
+      Format::V2 { .. } => 2,
+      Format::V3 { .. } => 3,
+    }
+  }
+}
+
+impl Header {
+  pub(crate) fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_record(&mut bytes, TAG_KDF, &self.kdf.to_bytes());
+    write_record(&mut bytes, TAG_CIPHER, &[match self.cipher {
+      CipherKind::Aes256Gcm => CIPHER_AES256GCM,
+    }]);
+    write_record(&mut bytes, TAG_CREATED, &self.created.to_be_bytes());
+    write_record(&mut bytes, TAG_SALT, &self.salt);
+    write_record(&mut bytes, TAG_NONCE, &self.nonce);
+    write_metadata(&mut bytes, &self.metadata);
+    bytes
+  }
+
+  fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+    let fields = Fields::parse(bytes)?;
+    Ok(Self {
+      kdf: fields.kdf.ok_or(FormatError::MissingField("kdf"))?,
+      cipher: fields.cipher.ok_or(FormatError::MissingField("cipher"))?,
+      created: fields.created.ok_or(FormatError::MissingField("created"))?,
+      salt: fields.salt.ok_or(FormatError::MissingField("salt"))?,
+      nonce: fields.nonce.ok_or(FormatError::MissingField("nonce"))?,
+      metadata: fields.metadata,
+    })
+  }
+}
+
+impl EnvelopeHeader {
+  pub(crate) fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_record(&mut bytes, TAG_CIPHER, &[match self.cipher {
+      CipherKind::Aes256Gcm => CIPHER_AES256GCM,
+    }]);
+    write_record(&mut bytes, TAG_CREATED, &self.created.to_be_bytes());
+    write_record(&mut bytes, TAG_NONCE, &self.nonce);
+    write_metadata(&mut bytes, &self.metadata);
+    bytes
+  }
+
+  fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+    let fields = Fields::parse(bytes)?;
+    // the key derivation settings belong to the password slots
+    if fields.kdf.is_some() {
+      return Err(FormatError::UnknownField(TAG_KDF));
+    }
+    if fields.salt.is_some() {
+      return Err(FormatError::UnknownField(TAG_SALT));
+    }
+    Ok(Self {
+      cipher: fields.cipher.ok_or(FormatError::MissingField("cipher"))?,
+      created: fields.created.ok_or(FormatError::MissingField("created"))?,
+      nonce: fields.nonce.ok_or(FormatError::MissingField("nonce"))?,
+      metadata: fields.metadata,
+    })
+  }
+}
+
+impl Slot {
+  fn to_record(&self) -> (u8, Vec<u8>) {
+    match self {
+      Slot::Password { kdf, salt, nonce, wrapped_key } => {
+        (SLOT_PASSWORD, [salt.as_slice(), nonce, wrapped_key, &kdf.to_bytes()].concat())
+      },
+      Slot::X25519 { recipient, ephemeral, wrapped_key } => {
+        (SLOT_X25519, [recipient.as_slice(), ephemeral, wrapped_key].concat())
+      },
+      Slot::Master { nonce, wrapped_key } => (SLOT_MASTER, [nonce.as_slice(), wrapped_key].concat()),
+      Slot::Keyfile { salt, nonce, wrapped_key } => (SLOT_KEYFILE, [salt.as_slice(), nonce, wrapped_key].concat()),
+    }
+  }
+
+  fn parse(tag: u8, value: &[u8]) -> Result<Self, FormatError> {
+    let mut reader = Reader(value);
+    let slot = match tag {
+      SLOT_PASSWORD => Slot::Password {
+        salt: reader.take(SALT_LEN, "password slot")?.to_vec(),
+        nonce: reader.take(NONCE_LEN, "password slot")?.try_into().unwrap(),
+        wrapped_key: reader.take(WRAPPED_KEY_LEN, "password slot")?.to_vec(),
+        kdf: Kdf::from_bytes(std::mem::take(&mut reader.0))
+          .map_err(|err| FormatError::InvalidField("kdf", err))?,
+      },
+      SLOT_X25519 => Slot::X25519 {
+        recipient: reader.take(32, "x25519 slot")?.try_into().unwrap(),
+        ephemeral: reader.take(32, "x25519 slot")?.try_into().unwrap(),
+        wrapped_key: reader.take(WRAPPED_KEY_LEN, "x25519 slot")?.to_vec(),
+      },
+      SLOT_MASTER => Slot::Master {
+        nonce: reader.take(NONCE_LEN, "master slot")?.try_into().unwrap(),
+        wrapped_key: reader.take(WRAPPED_KEY_LEN, "master slot")?.to_vec(),
+      },
+      SLOT_KEYFILE => Slot::Keyfile {
+        salt: reader.take(SALT_LEN, "keyfile slot")?.to_vec(),
+        nonce: reader.take(NONCE_LEN, "keyfile slot")?.try_into().unwrap(),
+        wrapped_key: reader.take(WRAPPED_KEY_LEN, "keyfile slot")?.to_vec(),
+      },
+      _ => return Err(FormatError::UnknownSlot(tag)),
+    };
+    if !reader.0.is_empty() {
+      return Err(FormatError::InvalidField("slot", format!("{} trailing bytes", reader.0.len())));
+    }
+    Ok(slot)
+  }
+}
+
+/// Parse the password slots of a keyring's master key file.
+pub(crate) fn parse_master(contents: &[u8]) -> Result<Vec<Slot>, FormatError> {
+  if contents.is_empty() {
+    return Err(FormatError::Empty);
+  }
+  let mut reader = Reader(contents);
+  match reader.u8("version")? {
+    1 => {
+      let slots = parse_slots(reader.0)?;
+      if !slots.iter().all(|slot| matches!(slot, Slot::Password { .. })) {
+        return Err(FormatError::InvalidField("slot", "master keys may only be wrapped under passwords".to_string()));
+      }
+      Ok(slots)
+    },
+    ver => Err(FormatError::UnknownVersion(ver)),
+  }
+}
+
+pub(crate) fn write_master(slots: &[Slot]) -> Vec<u8> {
+  [vec![1u8], write_slots(slots)].concat()
+}
+
+fn write_slots(slots: &[Slot]) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  for slot in slots {
+    let (tag, value) = slot.to_record();
+    write_record(&mut bytes, tag, &value);
+  }
+  bytes
+}
+
+fn parse_slots(bytes: &[u8]) -> Result<Vec<Slot>, FormatError> {
+  let mut reader = Reader(bytes);
+  let mut slots = vec![];
+  while !reader.0.is_empty() {
+    let tag = reader.u8("slot")?;
+    let len = reader.u16("slot")?;
+    slots.push(Slot::parse(tag, reader.take(len as usize, "slot")?)?);
+  }
+  if slots.is_empty() {
+    return Err(FormatError::MissingField("slot"));
+  }
+  Ok(slots)
+}
+
+/// Records metadata about the secret. Fields other than the kind are only written if they differ from
+/// their defaults, so keystores not making use of them remain readable by older releases, while those
+/// which do fail closed.
+fn write_metadata(bytes: &mut Vec<u8>, metadata: &Metadata) {
+  write_record(bytes, TAG_KIND, &[match metadata.kind {
+    SecretKind::Raw => KIND_RAW,
+    SecretKind::Bip39Seed => KIND_BIP39_SEED,
+  }]);
+
+  let encoding = match metadata.encoding {
+    Encoding::Hex => None,
+    Encoding::Utf8 => Some(ENCODING_UTF8),
+    Encoding::Base64 => Some(ENCODING_BASE64),
+    Encoding::Base58 => Some(ENCODING_BASE58),
+    Encoding::File => Some(ENCODING_FILE),
+  };
+  if let Some(encoding) = encoding {
+    write_record(bytes, TAG_ENCODING, &[encoding]);
+  }
+
+  let policy = &metadata.policy;
+  if let Some(not_after) = policy.not_after {
+    write_record(bytes, TAG_NOT_AFTER, &not_after.to_be_bytes());
+  }
+  if let Some(max_reads) = policy.max_reads {
+    write_record(bytes, TAG_MAX_READS, &max_reads.to_be_bytes());
+    write_record(bytes, TAG_READS, &policy.reads.to_be_bytes());
+  }
+  let flags = if policy.confirm { FLAG_CONFIRM } else { 0 }
+    | if policy.refuse_silent { FLAG_REFUSE_SILENT } else { 0 };
+  if flags != 0 {
+    write_record(bytes, TAG_POLICY_FLAGS, &[flags]);
+  }
+}
+
+/// Header records of any version, each present at most once.
+struct Fields {
+  kdf: Option<Kdf>,
+  cipher: Option<CipherKind>,
+  created: Option<u64>,
+  salt: Option<Vec<u8>>,
+  nonce: Option<[u8; NONCE_LEN]>,
+  metadata: Metadata,
+}
+
+impl Fields {
+  fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+    let mut reader = Reader(bytes);
+    let mut kdf = None;
+    let mut cipher = None;
+    let mut created = None;
+    let mut salt = None;
+    let mut nonce = None;
+    let mut kind = None;
+    let mut encoding = None;
+    let mut not_after = None;
+    let mut max_reads = None;
+    let mut reads = None;
+    let mut flags = None;
+
+    while !reader.0.is_empty() {
+      let tag = reader.u8("header record")?;
+      let len = reader.u16("header record")?;
+      let value = reader.take(len as usize, "header record")?;
+
+      match tag {
+        TAG_KDF => set_once(&mut kdf, "kdf", Kdf::from_bytes(value)
+          .map_err(|err| FormatError::InvalidField("kdf", err))?)?,
+        TAG_CIPHER => set_once(&mut cipher, "cipher", match value {
+          [CIPHER_AES256GCM] => CipherKind::Aes256Gcm,
+          _ => return Err(FormatError::InvalidField("cipher", "unknown cipher".to_string())),
+        })?,
+        TAG_CREATED => set_once(&mut created, "created", u64::from_be_bytes(value.try_into()
+          .map_err(|_| FormatError::InvalidField("created", format!("expected 8 bytes, got {}", len)))?))?,
+        TAG_SALT => {
+          if value.len() != SALT_LEN {
+            return Err(FormatError::InvalidField("salt", format!("expected {} bytes, got {}", SALT_LEN, len)));
+          }
+          set_once(&mut salt, "salt", value.to_vec())?
+        },
+        TAG_NONCE => set_once(&mut nonce, "nonce", value.try_into()
+          .map_err(|_| FormatError::InvalidField("nonce", format!("expected {} bytes, got {}", NONCE_LEN, len)))?)?,
+        TAG_KIND => set_once(&mut kind, "kind", match value {
+          [KIND_RAW] => SecretKind::Raw,
+          [KIND_BIP39_SEED] => SecretKind::Bip39Seed,
+          _ => return Err(FormatError::InvalidField("kind", "unknown secret kind".to_string())),
+        })?,
+        TAG_ENCODING => set_once(&mut encoding, "encoding", match value {
+          [ENCODING_UTF8] => Encoding::Utf8,
+          [ENCODING_BASE64] => Encoding::Base64,
+          [ENCODING_BASE58] => Encoding::Base58,
+          [ENCODING_FILE] => Encoding::File,
+          _ => return Err(FormatError::InvalidField("encoding", "unknown encoding".to_string())),
+        })?,
+        TAG_NOT_AFTER => set_once(&mut not_after, "not_after", u64::from_be_bytes(value.try_into()
+          .map_err(|_| FormatError::InvalidField("not_after", format!("expected 8 bytes, got {}", len)))?))?,
+        TAG_MAX_READS => set_once(&mut max_reads, "max_reads", u32::from_be_bytes(value.try_into()
+          .map_err(|_| FormatError::InvalidField("max_reads", format!("expected 4 bytes, got {}", len)))?))?,
+        TAG_READS => set_once(&mut reads, "reads", u32::from_be_bytes(value.try_into()
+          .map_err(|_| FormatError::InvalidField("reads", format!("expected 4 bytes, got {}", len)))?))?,
+        TAG_POLICY_FLAGS => set_once(&mut flags, "policy_flags", match value {
+          [flags] if flags & !(FLAG_CONFIRM | FLAG_REFUSE_SILENT) == 0 => *flags,
+          _ => return Err(FormatError::InvalidField("policy_flags", "unknown policy flags".to_string())),
+        })?,
+        _ => return Err(FormatError::UnknownField(tag)),
+      }
+    }
+
+    // the read count is tracked if & only if there is a read limit, so it cannot be lost on rewrite
+    if max_reads.is_some() != reads.is_some() {
+      return Err(FormatError::MissingField(if max_reads.is_some() { "reads" } else { "max_reads" }));
+    }
+    let flags = flags.unwrap_or_default();
+
+    Ok(Self {
+      kdf,
+      cipher,
+      created,
+      salt,
+      nonce,
+      // keystores written before secret kinds were introduced only hold raw secrets
+      metadata: Metadata {
+        kind: kind.unwrap_or_default(),
+        encoding: encoding.unwrap_or_default(),
+        policy: Policy {
+          not_after,
+          max_reads,
+          reads: reads.unwrap_or_default(),
+          confirm: flags & FLAG_CONFIRM != 0,
+          refuse_silent: flags & FLAG_REFUSE_SILENT != 0,
+        },
+      },
+    })
+  }
+}
+
+/// Cursor over the bytes of a keystore which fails instead of panicking when running out of input.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize, what: &'static str) -> Result<&'a [u8], FormatError> {
+    if self.0.len() < len {
+      return Err(FormatError::Truncated(what));
+    }
+    let (head, tail) = self.0.split_at(len);
+    self.0 = tail;
+    Ok(head)
+  }
+
+  fn u8(&mut self, what: &'static str) -> Result<u8, FormatError> {
+    Ok(self.take(1, what)?[0])
+  }
+
+  fn u16(&mut self, what: &'static str) -> Result<u16, FormatError> {
+    let bytes = self.take(2, what)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+}
+
+fn ciphertext(bytes: &[u8]) -> Result<Vec<u8>, FormatError> {
+  if bytes.len() < TAG_LEN {
+    return Err(FormatError::Truncated("ciphertext"));
+  }
+  Ok(bytes.to_vec())
+}
+
+fn set_once<T>(slot: &mut Option<T>, field: &'static str, value: T) -> Result<(), FormatError> {
+  if slot.is_some() {
+    return Err(FormatError::DuplicateField(field));
+  }
+  *slot = Some(value);
+  Ok(())
+}
+
+fn write_record(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+  bytes.push(tag);
+  bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+  bytes.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn metadata() -> Metadata {
+    Metadata {
+      kind: SecretKind::Bip39Seed,
+      encoding: Encoding::Base58,
+      policy: Policy {
+        not_after: Some(1_900_000_000),
+        max_reads: Some(3),
+        reads: 1,
+        confirm: true,
+        refuse_silent: true,
+      },
+    }
+  }
+
+  fn header() -> Header {
+    Header {
+      kdf: Kdf::Scrypt { log_n: 15, block_size: 8, parallelism: 1 },
+      cipher: CipherKind::Aes256Gcm,
+      created: 1_700_000_000,
+      salt: vec![1; SALT_LEN],
+      nonce: [2; NONCE_LEN],
+      metadata: metadata(),
+    }
+  }
+
+  fn slots() -> Vec<Slot> {
+    vec![
+      Slot::Password {
+        kdf: Kdf::Argon2id { memory_cost: 19456, time_cost: 2, parallelism: 1 },
+        salt: vec![3; SALT_LEN],
+        nonce: [4; NONCE_LEN],
+        wrapped_key: vec![5; WRAPPED_KEY_LEN],
+      },
+      Slot::X25519 { recipient: [6; 32], ephemeral: [7; 32], wrapped_key: vec![8; WRAPPED_KEY_LEN] },
+      Slot::Master { nonce: [9; NONCE_LEN], wrapped_key: vec![10; WRAPPED_KEY_LEN] },
+      Slot::Keyfile { salt: vec![11; SALT_LEN], nonce: [12; NONCE_LEN], wrapped_key: vec![13; WRAPPED_KEY_LEN] },
+    ]
+  }
+
+  fn v2() -> Vec<u8> {
+    let header = header().to_bytes();
+    [vec![2], (header.len() as u16).to_be_bytes().to_vec(), header, vec![14; 48]].concat()
+  }
+
+  fn v3() -> Vec<u8> {
+    let header = EnvelopeHeader { cipher: CipherKind::Aes256Gcm, created: 1_700_000_000, nonce: [2; NONCE_LEN], metadata: metadata() };
+    let header = header.to_bytes();
+    let slots = write_slots(&slots());
+    [
+      vec![3],
+      (header.len() as u16).to_be_bytes().to_vec(),
+      header,
+      (slots.len() as u16).to_be_bytes().to_vec(),
+      slots,
+      vec![14; 48],
+    ].concat()
+  }
+
+  #[test]
+  fn round_trips_v1() {
+    let bytes = [vec![1], vec![1; SALT_LEN], vec![2; NONCE_LEN], vec![3; 48]].concat();
+    let Format::V1 { salt, nonce, ciphertext } = Format::parse(&bytes).unwrap() else { panic!("expected v1") };
+    assert_eq!((salt, nonce, ciphertext.len()), ([1; SALT_LEN], [2; NONCE_LEN], 48));
+    assert_eq!(Format::parse(&bytes).unwrap().to_bytes(), bytes);
+  }
+
+  #[test]
+  fn round_trips_v2() {
+    let bytes = v2();
+    let format = Format::parse(&bytes).unwrap();
+    let Format::V2 { header: parsed, .. } = &format else { panic!("expected v2") };
+    let expected = header();
+    assert_eq!(parsed.kdf, expected.kdf);
+    assert_eq!((parsed.created, &parsed.salt, parsed.nonce), (expected.created, &expected.salt, expected.nonce));
+    assert_eq!(parsed.metadata, expected.metadata);
+    assert_eq!(format.to_bytes(), bytes);
+  }
+
+  #[test]
+  fn round_trips_v3() {
+    let bytes = v3();
+    let format = Format::parse(&bytes).unwrap();
+    let Format::V3 { header, slots: parsed, .. } = &format else { panic!("expected v3") };
+    assert_eq!(header.metadata, metadata());
+    assert_eq!(parsed, &slots());
+    assert_eq!(format.to_bytes(), bytes);
+  }
+
+  #[test]
+  fn round_trips_master_file() {
+    let slots = slots()[..1].to_vec();
+    assert_eq!(parse_master(&write_master(&slots)).unwrap(), slots);
+    assert!(matches!(parse_master(&write_master(&self::slots())), Err(FormatError::InvalidField("slot", _))));
+  }
+
+  #[test]
+  fn rejects_malformed_files() {
+    assert_eq!(Format::parse(&[]).unwrap_err(), FormatError::Empty);
+    assert_eq!(Format::parse(&[9]).unwrap_err(), FormatError::UnknownVersion(9));
+    assert_eq!(Format::parse(&[1, 0, 0]).unwrap_err(), FormatError::Truncated("salt"));
+    assert_eq!(Format::parse(&[2, 0]).unwrap_err(), FormatError::Truncated("header length"));
+
+    // every truncation of a valid file is rejected
+    for bytes in [v2(), v3()] {
+      let ciphertext_start = bytes.len() - 48;
+      for len in 0..ciphertext_start {
+        assert!(Format::parse(&bytes[..len]).is_err(), "{} bytes", len);
+      }
+    }
+
+    let mut header = header().to_bytes();
+    write_record(&mut header, TAG_CREATED, &0u64.to_be_bytes());
+    assert_eq!(Header::parse(&header).unwrap_err(), FormatError::DuplicateField("created"));
+
+    let mut header = self::header().to_bytes();
+    write_record(&mut header, 200, &[]);
+    assert_eq!(Header::parse(&header).unwrap_err(), FormatError::UnknownField(200));
+
+    let mut slots = vec![];
+    write_record(&mut slots, 200, &[]);
+    assert_eq!(parse_slots(&slots).unwrap_err(), FormatError::UnknownSlot(200));
+  }
+
+  #[test]
+  fn rejects_excessive_kdf_parameters() {
+    let kdfs = [
+      Kdf::Pbkdf2Sha256 { rounds: u32::MAX },
+      Kdf::Argon2id { memory_cost: u32::MAX, time_cost: 2, parallelism: 1 },
+      Kdf::Argon2id { memory_cost: 19456, time_cost: u32::MAX, parallelism: 1 },
+      Kdf::Scrypt { log_n: 63, block_size: 8, parallelism: 1 },
+      Kdf::Scrypt { log_n: 20, block_size: u32::MAX, parallelism: 1 },
+    ];
+    for kdf in kdfs {
+      let header = Header { kdf, ..header() }.to_bytes();
+      assert!(matches!(Header::parse(&header), Err(FormatError::InvalidField("kdf", _))));
+    }
+  }
+
+  #[test]
+  fn checks_policy() {
+    assert!(Policy::default().check("key").is_ok());
+    assert!(Policy { not_after: Some(date::now() + 60), ..Policy::default() }.check("key").is_ok());
+    assert!(matches!(Policy { not_after: Some(date::now() - 60), ..Policy::default() }.check("key"), Err(Error::Denied(_))));
+    assert!(Policy { max_reads: Some(2), reads: 1, ..Policy::default() }.check("key").is_ok());
+    assert!(matches!(Policy { max_reads: Some(2), reads: 2, ..Policy::default() }.check("key"), Err(Error::Denied(_))));
+    assert!(matches!(Policy { max_reads: Some(0), ..Policy::default() }.check("key"), Err(Error::Denied(_))));
+    // interactive restrictions are enforced by the CLI
+    assert!(Policy { confirm: true, refuse_silent: true, ..Policy::default() }.check("key").is_ok());
+  }
+
+  #[test]
+  fn detects_relaxed_policies() {
+    let policy = metadata().policy;
+    assert!(!policy.is_relaxed_by(&policy));
+    assert!(!policy.is_relaxed_by(&Policy { not_after: Some(1_800_000_000), ..policy.clone() }));
+    assert!(!policy.is_relaxed_by(&Policy { max_reads: Some(2), ..policy.clone() }));
+    assert!(!Policy::default().is_relaxed_by(&policy));
+
+    assert!(policy.is_relaxed_by(&Policy { not_after: Some(2_000_000_000), ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { not_after: None, ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { max_reads: Some(4), ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { max_reads: None, reads: 0, ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { reads: 0, ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { confirm: false, ..policy.clone() }));
+    assert!(policy.is_relaxed_by(&Policy { refuse_silent: false, ..policy.clone() }));
+  }
+}
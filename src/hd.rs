@@ -0,0 +1,98 @@
+//! BIP-39 mnemonics & BIP-32/BIP-44 hierarchical deterministic key derivation.
+use bip32::{DerivationPath, XPrv};
+use bip39::{Language, Mnemonic};
+use pbkdf2::hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+use crate::error::{Error, Result};
+
+/// Elliptic curve of the keys to derive. Determines the derivation scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+  /// BIP-32 derivation.
+  Secp256k1,
+  /// SLIP-10 derivation, which only supports hardened children.
+  Ed25519,
+}
+
+/// Validate the wordlist & checksum of an English BIP-39 mnemonic and derive its seed.
+pub fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Result<[u8; 64]> {
+  let mnemonic = Mnemonic::parse_in(Language::English, phrase)
+    .map_err(|err| Error::InvalidValue(format!("Invalid mnemonic: {}", err)))?;
+  Ok(mnemonic.to_seed(passphrase))
+}
+
+/// BIP-44 derivation path & curve of the given chain preset with the given address index, e.g.
+/// `eth` for `m/44'/60'/0'/0/<index>`.
+pub fn preset(preset: &str, index: u32) -> Option<(String, Curve)> {
+  let coin_type = match preset {
+    // BIP-84, as wallets derive native segwit addresses from it rather than BIP-44
+    "bitcoin" => return Some((format!("m/84'/0'/0'/0/{}", index), Curve::Secp256k1)),
+    "eth" => 60,
+    "cosmos" => 118,
+    // as used by the Solana CLI & Phantom
+    "solana" => return Some((format!("m/44'/501'/{}'/0'", index), Curve::Ed25519)),
+    _ => return None,
+  };
+  Some((format!("m/44'/{}'/0'/0/{}", coin_type, index), Curve::Secp256k1))
+}
+
+/// Derive the private key at the given derivation path, e.g. `m/44'/60'/0'/0/0`, from a seed.
+pub fn derive(seed: &[u8], path: &str, curve: Curve) -> Result<[u8; 32]> {
+  let parsed: DerivationPath = path.parse()
+    .map_err(|_| Error::InvalidValue(format!("Invalid derivation path {}", path)))?;
+
+  match curve {
+    Curve::Secp256k1 => {
+      let xprv = XPrv::derive_from_path(seed, &parsed)
+        .map_err(|err| Error::InvalidValue(format!("Failed to derive key: {}", err)))?;
+      Ok(xprv.private_key().to_bytes().into())
+    },
+    Curve::Ed25519 => {
+      let (mut key, mut chain_code) = hmac_sha512(b"ed25519 seed", &[seed]);
+      for child in parsed.iter() {
+        if !child.is_hardened() {
+          return Err(Error::InvalidValue(format!("Ed25519 derivation path {} must be fully hardened", path)));
+        }
+        (key, chain_code) = hmac_sha512(&chain_code, &[&[0], &key, &u32::from(child).to_be_bytes()]);
+      }
+      Ok(key)
+    },
+  }
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+  let mut mac = Hmac::<Sha512>::new_from_slice(key).unwrap();
+  for chunk in data {
+    mac.update(chunk);
+  }
+  let res = mac.finalize().into_bytes();
+  (res[..32].try_into().unwrap(), res[32..].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::address::Chain;
+
+  const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+  fn address(chain: &str, index: u32) -> String {
+    let seed = mnemonic_to_seed(MNEMONIC, "").unwrap();
+    let (path, curve) = preset(chain, index).unwrap();
+    let key = derive(&seed, &path, curve).unwrap();
+    chain.parse::<Chain>().unwrap().account(&key).unwrap().address
+  }
+
+  #[test]
+  fn derives_bip84_bitcoin_addresses() {
+    // BIP-84 test vectors
+    assert_eq!(address("bitcoin", 0), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+    assert_eq!(address("bitcoin", 1), "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+  }
+
+  #[test]
+  fn derives_eth_addresses() {
+    assert_eq!(address("eth", 0), "0x9858EfFD232B4033E47d90003D41EC34EcaEda94");
+  }
+}
@@ -0,0 +1,261 @@
+use pbkdf2::hmac::Hmac;
+use sha2::Sha256;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{Error, Result};
+
+/// Number of PBKDF2 rounds assumed for legacy v1 keystores, which do not record it.
+pub const DEFAULT_LEGACY_ROUNDS: u32 = 100_000;
+
+const KDF_PBKDF2_SHA256: u8 = 1;
+const KDF_ARGON2ID: u8 = 2;
+const KDF_SCRYPT: u8 = 3;
+
+// upper bounds on the parameters, so a corrupt or hostile keystore cannot exhaust memory or CPU
+const MAX_PBKDF2_ROUNDS: u32 = 10_000_000;
+const MAX_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+const MAX_ARGON2_TIME_COST: u32 = 100;
+const MAX_PARALLELISM: u32 = 16;
+
+/// Key derivation function & its parameters as recorded in a keystore header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kdf {
+  Pbkdf2Sha256 { rounds: u32 },
+  Argon2id { memory_cost: u32, time_cost: u32, parallelism: u32 },
+  Scrypt { log_n: u8, block_size: u32, parallelism: u32 },
+}
+
+impl Default for Kdf {
+  fn default() -> Self {
+    Kdf::Argon2id {
+      memory_cost: argon2::Params::DEFAULT_M_COST,
+      time_cost: argon2::Params::DEFAULT_T_COST,
+      parallelism: argon2::Params::DEFAULT_P_COST,
+    }
+  }
+}
+
+impl Kdf {
+  /// Derive a 256-bit encryption key from the given password. The password is NFC-normalized first.
+  pub fn derive(&self, password: impl AsRef<str>, salt: &[u8]) -> Result<[u8; 32]> {
+    self.validate().map_err(Error::InvalidValue)?;
+    match self {
+      Kdf::Pbkdf2Sha256 { rounds } => Ok(derive_key_v1(password, salt, *rounds)),
+      Kdf::Argon2id { memory_cost, time_cost, parallelism } => {
+        let password = password.as_ref().nfc().collect::<String>();
+        let params = argon2::Params::new(*memory_cost, *time_cost, *parallelism, Some(32))
+          .map_err(|err| Error::InvalidValue(format!("Invalid Argon2id parameters: {}", err)))?;
+        let argon = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let mut res = [0u8; 32];
+        argon.hash_password_into(password.as_bytes(), salt, &mut res)
+          .map_err(|err| Error::InvalidValue(format!("Failed to derive key: {}", err)))?;
+        Ok(res)
+      },
+      Kdf::Scrypt { log_n, block_size, parallelism } => {
+        let password = password.as_ref().nfc().collect::<String>();
+        let params = scrypt::Params::new(*log_n, *block_size, *parallelism, 32)
+          .map_err(|err| Error::InvalidValue(format!("Invalid scrypt parameters: {}", err)))?;
+        let mut res = [0u8; 32];
+        scrypt::scrypt(password.as_bytes(), salt, &params, &mut res)
+          .map_err(|err| Error::InvalidValue(format!("Failed to derive key: {}", err)))?;
+        Ok(res)
+      },
+    }
+  }
+
+  /// Check the parameters against upper bounds of at most 4 GiB of memory & a sensible number of rounds.
+  pub fn validate(&self) -> std::result::Result<(), String> {
+    match *self {
+      Kdf::Pbkdf2Sha256 { rounds } => {
+        if !(1..=MAX_PBKDF2_ROUNDS).contains(&rounds) {
+          return Err(format!("PBKDF2 rounds must be between 1 and {}", MAX_PBKDF2_ROUNDS));
+        }
+      },
+      Kdf::Argon2id { memory_cost, time_cost, parallelism } => {
+        if u64::from(memory_cost) * 1024 > MAX_MEMORY {
+          return Err(format!("Argon2id memory cost must be at most {} KiB", MAX_MEMORY / 1024));
+        }
+        if time_cost > MAX_ARGON2_TIME_COST {
+          return Err(format!("Argon2id time cost must be at most {}", MAX_ARGON2_TIME_COST));
+        }
+        if parallelism > MAX_PARALLELISM {
+          return Err(format!("Argon2id parallelism must be at most {}", MAX_PARALLELISM));
+        }
+      },
+      Kdf::Scrypt { log_n, block_size, parallelism } => {
+        // scrypt requires 128 * r * N bytes, which exceeds the bound for any r if log_n > 25
+        if log_n > 25 || (128 * u64::from(block_size)) << log_n > MAX_MEMORY {
+          return Err("scrypt parameters must require at most 4 GiB of memory".to_string());
+        }
+        if parallelism > MAX_PARALLELISM {
+          return Err(format!("scrypt parallelism must be at most {}", MAX_PARALLELISM));
+        }
+      },
+    }
+    Ok(())
+  }
+
+  pub(crate) fn to_bytes(&self) -> Vec<u8> {
+    match self {
+      Kdf::Pbkdf2Sha256 { rounds } => [vec![KDF_PBKDF2_SHA256], rounds.to_be_bytes().to_vec()].concat(),
+      Kdf::Argon2id { memory_cost, time_cost, parallelism } => [
+        vec![KDF_ARGON2ID],
+        memory_cost.to_be_bytes().to_vec(),
+        time_cost.to_be_bytes().to_vec(),
+        parallelism.to_be_bytes().to_vec(),
+      ].concat(),
+      Kdf::Scrypt { log_n, block_size, parallelism } => [
+        vec![KDF_SCRYPT, *log_n],
+        block_size.to_be_bytes().to_vec(),
+        parallelism.to_be_bytes().to_vec(),
+      ].concat(),
+    }
+  }
+
+  /// Parse the key derivation function of a keystore header. Parameters beyond the bounds of
+  /// [`Kdf::validate`] are rejected.
+  pub(crate) fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, String> {
+    let kdf = Self::parse(bytes)?;
+    kdf.validate()?;
+    Ok(kdf)
+  }
+
+  fn parse(bytes: &[u8]) -> std::result::Result<Self, String> {
+    let u32_at = |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    match bytes.first() {
+      Some(&KDF_PBKDF2_SHA256) => {
+        if bytes.len() != 5 {
+          return Err("Invalid PBKDF2 parameters".to_string());
+        }
+        Ok(Kdf::Pbkdf2Sha256 { rounds: u32_at(1) })
+      },
+      Some(&KDF_ARGON2ID) => {
+        if bytes.len() != 13 {
+          return Err("Invalid Argon2id parameters".to_string());
+        }
+        Ok(Kdf::Argon2id { memory_cost: u32_at(1), time_cost: u32_at(5), parallelism: u32_at(9) })
+      },
+      Some(&KDF_SCRYPT) => {
+        if bytes.len() != 10 {
+          return Err("Invalid scrypt parameters".to_string());
+        }
+        Ok(Kdf::Scrypt { log_n: bytes[1], block_size: u32_at(2), parallelism: u32_at(6) })
+      },
+      Some(id) => Err(format!("Unknown key derivation function {}", id)),
+      None => Err("Missing key derivation function".to_string()),
+    }
+  }
+}
+
+pub(crate) fn derive_key_v1(password: impl AsRef<str>, salt: &[u8], rounds: u32) -> [u8; 32] {
+  let password = password.as_ref().nfc().collect::<String>();
+  let bytes = password.as_bytes();
+  let mut res = [0u8; 32];
+  pbkdf2::pbkdf2::<Hmac<Sha256>>(bytes, salt, rounds, &mut res).unwrap();
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn derive(kdf: Kdf, password: &str, salt: &[u8]) -> String {
+    hex::encode(kdf.derive(password, salt).unwrap())
+  }
+
+  #[test]
+  fn derives_argon2id_vectors() {
+    // from the test suite of the Argon2 reference implementation
+    let kdf = |memory_cost| Kdf::Argon2id { memory_cost, time_cost: 2, parallelism: 1 };
+    assert_eq!(derive(kdf(1 << 16), "password", b"somesalt"), "09316115d5cf24ed5a15a31a3ba326e5cf32edc24702987c02b6566f61913cf7");
+    assert_eq!(derive(kdf(1 << 8), "password", b"somesalt"), "9dfeb910e80bad0311fee20f9c0e2b12c17987b4cac90c2ef54d5b3021c68bfe");
+    assert_eq!(derive(kdf(1 << 16), "differentpassword", b"somesalt"), "0b84d652cf6b0c4beaef0dfe278ba6a80df6696281d7e0d2891b817d8c458fde");
+    assert_eq!(derive(kdf(1 << 16), "password", b"diffsalt"), "bdf32b05ccc42eb15d58fd19b1f856b113da1e9a5874fdcc544308565aa8141c");
+  }
+
+  #[test]
+  fn derives_scrypt_vectors() {
+    // RFC 7914, truncated to 32 bytes
+    let kdf = |log_n, block_size, parallelism| Kdf::Scrypt { log_n, block_size, parallelism };
+    assert_eq!(derive(kdf(4, 1, 1), "", b""), "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442");
+    assert_eq!(derive(kdf(10, 8, 16), "password", b"NaCl"), "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162");
+    assert_eq!(derive(kdf(14, 8, 1), "pleaseletmein", b"SodiumChloride"), "7023bdcb3afd7348461c06cd81fd38ebfda8fbba904f8e3ea9b543f6545da1f2");
+  }
+
+  #[test]
+  fn derives_pbkdf2_vectors() {
+    // RFC 7914, truncated to 32 bytes
+    let kdf = |rounds| Kdf::Pbkdf2Sha256 { rounds };
+    assert_eq!(derive(kdf(1), "passwd", b"salt"), "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+    assert_eq!(derive(kdf(80000), "Password", b"NaCl"), "4ddcd8f60b98be21830cee5ef22701f9641a4418d04c0414aeff08876b34ab56");
+  }
+
+  #[test]
+  fn normalizes_passwords() {
+    for kdf in [Kdf::Pbkdf2Sha256 { rounds: 1 }, Kdf::Argon2id { memory_cost: 8, time_cost: 1, parallelism: 1 }] {
+      assert_eq!(derive(kdf.clone(), "caf\u{e9}", b"saltsalt"), derive(kdf, "cafe\u{301}", b"saltsalt"));
+    }
+  }
+
+  #[test]
+  fn round_trips() {
+    for kdf in [
+      Kdf::Pbkdf2Sha256 { rounds: 100_000 },
+      Kdf::default(),
+      Kdf::Scrypt { log_n: 17, block_size: 8, parallelism: 1 },
+    ] {
+      assert_eq!(Kdf::from_bytes(&kdf.to_bytes()), Ok(kdf));
+    }
+  }
+
+  #[test]
+  fn rejects_malformed_parameters() {
+    for kdf in [Kdf::Pbkdf2Sha256 { rounds: 1 }, Kdf::default(), Kdf::Scrypt { log_n: 1, block_size: 1, parallelism: 1 }] {
+      let bytes = kdf.to_bytes();
+      assert!(Kdf::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+      assert!(Kdf::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+    assert!(Kdf::from_bytes(&[]).is_err());
+    assert!(Kdf::from_bytes(&[4, 0, 0, 0, 1]).is_err());
+  }
+
+  #[test]
+  fn bounds_parameters() {
+    let argon2 = |memory_cost, time_cost, parallelism| Kdf::Argon2id { memory_cost, time_cost, parallelism };
+    let scrypt = |log_n, block_size, parallelism| Kdf::Scrypt { log_n, block_size, parallelism };
+    let valid = [
+      Kdf::Pbkdf2Sha256 { rounds: 1 },
+      Kdf::Pbkdf2Sha256 { rounds: MAX_PBKDF2_ROUNDS },
+      argon2(4 * 1024 * 1024, MAX_ARGON2_TIME_COST, MAX_PARALLELISM),
+      scrypt(25, 1, MAX_PARALLELISM),
+      scrypt(22, 8, 1),
+    ];
+    for kdf in valid {
+      assert_eq!(kdf.validate(), Ok(()), "{:?}", kdf);
+      assert!(Kdf::from_bytes(&kdf.to_bytes()).is_ok(), "{:?}", kdf);
+    }
+
+    let invalid = [
+      // rounds
+      Kdf::Pbkdf2Sha256 { rounds: 0 },
+      Kdf::Pbkdf2Sha256 { rounds: MAX_PBKDF2_ROUNDS + 1 },
+      // memory
+      argon2(4 * 1024 * 1024 + 1, 2, 1),
+      argon2(u32::MAX, 2, 1),
+      scrypt(26, 1, 1),
+      scrypt(23, 8, 1),
+      scrypt(u8::MAX, 8, 1),
+      scrypt(10, u32::MAX, 1),
+      // time cost
+      argon2(1024, MAX_ARGON2_TIME_COST + 1, 1),
+      // parallelism
+      argon2(1024, 2, MAX_PARALLELISM + 1),
+      scrypt(10, 8, MAX_PARALLELISM + 1),
+    ];
+    for kdf in invalid {
+      assert!(kdf.validate().is_err(), "{:?}", kdf);
+      assert!(Kdf::from_bytes(&kdf.to_bytes()).is_err(), "{:?}", kdf);
+      assert!(matches!(kdf.derive("password", b"saltsalt"), Err(Error::InvalidValue(_))), "{:?}", kdf);
+    }
+  }
+}
@@ -0,0 +1,505 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+
+use crate::error::{Error, Result};
+use crate::format::Metadata;
+use crate::kdf::{Kdf, DEFAULT_LEGACY_ROUNDS};
+use crate::keystore::{Credential, Keystore};
+use crate::master::MasterFile;
+
+/// Name of the file holding the master key of a keyring, if it has one.
+const MASTER_FILE: &str = ".master";
+
+/// Handle to a directory of keystores, one file per keystore named after it.
+#[derive(Debug)]
+pub struct Keyring {
+  dir: PathBuf,
+  legacy_rounds: u32,
+}
+
+impl Keyring {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      dir: dir.into(),
+      legacy_rounds: DEFAULT_LEGACY_ROUNDS,
+    }
+  }
+
+  /// Open the default keyring at `~/.kayring`.
+  pub fn open_default() -> Result<Self> {
+    let homedir = home::home_dir()
+      .ok_or_else(|| Error::Other("Could not determine the root directory".to_string()))?;
+    Ok(Self::new(homedir.join(".kayring")))
+  }
+
+  /// Set the number of PBKDF2 rounds used to decrypt legacy v1 keystores, which do not record it.
+  pub fn with_legacy_rounds(mut self, rounds: u32) -> Self {
+    self.legacy_rounds = rounds;
+    self
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Path of the file backing the keystore with the given name.
+  pub fn path(&self, name: &str) -> PathBuf {
+    self.dir.join(name)
+  }
+
+  pub fn exists(&self, name: &str) -> bool {
+    check_name(name).is_ok() && self.path(name).exists()
+  }
+
+  /// Sorted names of all keystores in this keyring, excluding the master key file & the temporary files of
+  /// interrupted writes, which are no valid keystore names. Entries which cannot be read are skipped.
+  pub fn list(&self) -> Result<Vec<String>> {
+    self.list_with_errors().map(|(names, _)| names)
+  }
+
+  /// Like [`Keyring::list`], but also returns whether some entries could not be read.
+  pub fn list_with_errors(&self) -> Result<(Vec<String>, bool)> {
+    let entries = fs::read_dir(&self.dir)
+      .map_err(|err| Error::io(format!("Could not read from directory {}", self.dir.to_string_lossy()), err))?;
+
+    let mut has_errs = false;
+    let mut names = entries
+      .filter_map(|entry| match entry {
+        Ok(entry) => Some(entry.file_name().to_string_lossy().to_string()),
+        Err(_) => {
+          has_errs = true;
+          None
+        },
+      })
+      .filter(|name| check_name(name).is_ok())
+      .collect::<Vec<_>>();
+    names.sort();
+    Ok((names, has_errs))
+  }
+
+  /// Read & parse the keystore with the given name without decrypting it.
+  pub fn open(&self, name: &str) -> Result<Keystore> {
+    check_name(name)?;
+    let filepath = self.path(name);
+    if !filepath.exists() {
+      return Err(Error::NotFound(name.to_string()));
+    }
+
+    let contents = fs::read(&filepath)
+      .map_err(|err| {
+        Error::io(format!("Could not read from file {}", filepath.to_string_lossy()), err)
+      })?;
+    let keystore = Keystore::from_bytes(&contents)
+      .map_err(|err| err.with_path(&filepath))?;
+    Ok(keystore.with_legacy_rounds(self.legacy_rounds))
+  }
+
+  /// Decrypt the secret stored in the keystore with the given name, enforcing the not-after date &
+  /// read limit of its [`crate::Policy`]. Reads of keystores with a read limit are counted, which rewrites
+  /// the keystore.
+  pub fn get(&self, name: &str, password: impl AsRef<str>) -> Result<Vec<u8>> {
+    self.get_with(name, &Credential::Password(password.as_ref().to_string()))
+  }
+
+  /// Like [`Keyring::get`], but unlocks the keystore with any credential. Passwords unlock the master
+  /// key for keystores wrapped under it.
+  pub fn get_with(&self, name: &str, credential: &Credential) -> Result<Vec<u8>> {
+    let keystore = self.open(name)?;
+    let mut metadata = keystore.metadata();
+    metadata.policy.check(name)?;
+
+    let credential = &self.unlock_for(&keystore, credential.clone())?;
+
+    let secret = keystore.unlock(credential)?;
+    if metadata.policy.max_reads.is_some() {
+      metadata.policy.reads += 1;
+      self.set(name, &keystore.reencrypt(&secret, credential, metadata)?, true)?;
+    }
+    Ok(secret)
+  }
+
+  /// Encrypt a new keystore for this keyring: under its master key if it has one, which is unlocked with
+  /// the password, or else under the password itself.
+  pub fn encrypt(&self, value: &[u8], password: impl AsRef<str>, kdf: Kdf, metadata: Metadata) -> Result<Keystore> {
+    match self.unlock(Credential::Password(password.as_ref().to_string()))? {
+      Credential::MasterKey(master) => Keystore::encrypt_with_master(value, &master, metadata),
+      _ => Keystore::encrypt_with(value, password, kdf, metadata),
+    }
+  }
+
+  pub fn has_master_key(&self) -> bool {
+    self.path(MASTER_FILE).exists()
+  }
+
+  /// Unlock the master key of this keyring with the password, if it has one, so decrypting many
+  /// keystores derives a key from the password only once. Other credentials are returned as is.
+  pub fn unlock(&self, credential: Credential) -> Result<Credential> {
+    match (self.master_file()?, credential) {
+      (Some(master), Credential::Password(password)) => Ok(Credential::MasterKey(master.unlock(password)?)),
+      (_, credential) => Ok(credential),
+    }
+  }
+
+  /// Like [`Keyring::unlock`], but only unlocks the master key if the given keystore is wrapped under it.
+  /// Passwords not unlocking the master key are returned as is, as they may unlock another slot of the
+  /// keystore, e.g. a recovery password.
+  pub fn unlock_for(&self, keystore: &Keystore, credential: Credential) -> Result<Credential> {
+    match credential {
+      Credential::Password(_) if keystore.uses_master_key() => match self.unlock(credential.clone()) {
+        Err(Error::WrongPassword) => Ok(credential),
+        res => res,
+      },
+      credential => Ok(credential),
+    }
+  }
+
+  /// Generate a master key wrapped under the password, & wrap the data keys of all keystores under it.
+  /// Existing keystores must share the password. If the keyring already has a master key, it is
+  /// unlocked instead, so an interrupted initialization can be resumed.
+  pub fn init_master(&self, password: impl AsRef<str>, kdf: Kdf) -> Result<()> {
+    let (master_file, master) = match self.master_file()? {
+      Some(master_file) => {
+        let master = master_file.unlock(&password)?;
+        (None, master)
+      },
+      None => {
+        let (master_file, master) = MasterFile::create(&password, kdf)?;
+        (Some(master_file), master)
+      },
+    };
+
+    // re-wrap everything first so we do not end up with a keyring using mixed passwords
+    let credential = Credential::Password(password.as_ref().to_string());
+    let names = if self.dir.exists() { self.list()? } else { vec![] };
+    let mut keystores = vec![];
+    for name in names {
+      let keystore = self.open(&name)?;
+      if !keystore.uses_master_key() {
+        keystores.push((name, keystore.wrap_with_master(&credential, &master)?));
+      }
+    }
+
+    if let Some(master_file) = master_file {
+      self.write(&self.path(MASTER_FILE), &master_file.to_bytes())?;
+    }
+    for (name, keystore) in keystores {
+      self.set(&name, &keystore, true)?;
+    }
+    Ok(())
+  }
+
+  /// Wrap the master key under a new password. Only the master key file is rewritten.
+  pub fn rekey_master(&self, password: impl AsRef<str>, new_password: impl AsRef<str>, kdf: Kdf) -> Result<()> {
+    let master_file = self.master_file()?
+      .ok_or_else(|| Error::NotFound(MASTER_FILE.to_string()))?;
+    self.write(&self.path(MASTER_FILE), &master_file.rekey(password, new_password, kdf)?.to_bytes())
+  }
+
+  fn master_file(&self) -> Result<Option<MasterFile>> {
+    let filepath = self.path(MASTER_FILE);
+    if !filepath.exists() {
+      return Ok(None);
+    }
+    let contents = fs::read(&filepath)
+      .map_err(|err| {
+        Error::io(format!("Could not read from file {}", filepath.to_string_lossy()), err)
+      })?;
+    MasterFile::from_bytes(&contents)
+      .map(Some)
+      .map_err(|err| err.with_path(&filepath))
+  }
+
+  /// Store the keystore under the given name. Fails if it already exists unless `overwrite`.
+  /// Existing keystores are replaced atomically, so a crash never leaves a half-written keystore
+  /// behind.
+  pub fn set(&self, name: &str, keystore: &Keystore, overwrite: bool) -> Result<()> {
+    check_name(name)?;
+    let filepath = self.path(name);
+    if filepath.exists() && !overwrite {
+      return Err(Error::AlreadyExists(name.to_string()));
+    }
+    self.write(&filepath, &keystore.to_bytes())
+  }
+
+  /// Atomically replace the file at the given path.
+  fn write(&self, filepath: &Path, contents: &[u8]) -> Result<()> {
+    fs::create_dir_all(&self.dir)
+      .map_err(|err| {
+        Error::io(format!("Failed to create the directory at {}", self.dir.to_string_lossy()), err)
+      })?;
+    write_private(filepath, contents)
+  }
+
+  /// Copy the keystore `from` to `to`, retaining its key, password & settings. Fails if `to`
+  /// already exists unless `overwrite`.
+  pub fn clone(&self, from: &str, to: &str, overwrite: bool) -> Result<()> {
+    check_name(from)?;
+    check_name(to)?;
+    let frompath = self.path(from);
+    let topath = self.path(to);
+
+    if !frompath.exists() {
+      return Err(Error::NotFound(from.to_string()));
+    }
+
+    if topath.exists() && !overwrite {
+      return Err(Error::AlreadyExists(to.to_string()));
+    }
+
+    let contents = fs::read(&frompath)
+      .map_err(|err| {
+        Error::io(format!("Could not read from file {}", frompath.to_string_lossy()), err)
+      })?;
+    self.write(&topath, &contents)
+  }
+}
+
+/// Ensure the keystore name refers to a file directly within the keyring directory, other than the master
+/// key file & the temporary files of [`write_private`].
+fn check_name(name: &str) -> Result<()> {
+  let internal = name.starts_with('.') || name.ends_with(".tmp");
+  if name.is_empty() || internal || name.contains(['/', '\\']) || name.contains("..") {
+    return Err(Error::InvalidValue(format!("Invalid keystore name {}", name)));
+  }
+  Ok(())
+}
+
+/// Atomically write a file only accessible by the current user. The contents are first written to a new file
+/// with a random name next to it, so an existing file or symlink is never written through.
+pub fn write_private(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+  let path = path.as_ref();
+  let mut tmpname = path.file_name().unwrap_or_default().to_os_string();
+  tmpname.push(format!(".{:016x}.tmp", OsRng.next_u64()));
+  let tmppath = path.with_file_name(tmpname);
+
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+  let mut file = options.open(&tmppath)
+    .map_err(|err| Error::io(format!("Could not write to file {}", tmppath.to_string_lossy()), err))?;
+  let written = file.write_all(contents).and_then(|_| file.sync_all())
+    .map_err(|err| Error::io(format!("Could not write to file {}", tmppath.to_string_lossy()), err))
+    .and_then(|_| fs::rename(&tmppath, path)
+      .map_err(|err| {
+        Error::io(format!("Could not rename {} to {}", tmppath.to_string_lossy(), path.to_string_lossy()), err)
+      }));
+  if written.is_err() {
+    let _ = fs::remove_file(&tmppath);
+  }
+  written
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::format::Policy;
+
+  fn kdf() -> Kdf {
+    Kdf::Pbkdf2Sha256 { rounds: 1000 }
+  }
+
+  fn keystore() -> Keystore {
+    Keystore::encrypt(&[1, 2, 3], "password", kdf()).unwrap()
+  }
+
+  #[test]
+  fn rejects_reserved_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyring = Keyring::new(dir.path().join("keyring"));
+    keyring.set("a", &keystore(), false).unwrap();
+    for name in ["", ".master", ".hidden", "a.tmp", "../a", "sub/a", "sub\\a", "a..b"] {
+      assert!(matches!(keyring.set(name, &keystore(), true), Err(Error::InvalidValue(_))), "{}", name);
+      assert!(matches!(keyring.open(name), Err(Error::InvalidValue(_))), "{}", name);
+      assert!(matches!(keyring.clone("a", name, true), Err(Error::InvalidValue(_))), "{}", name);
+      assert!(!keyring.exists(name), "{}", name);
+    }
+    assert_eq!(keyring.list().unwrap(), vec!["a"]);
+    assert!(!dir.path().join("a").exists());
+  }
+
+  fn keyring_with(names: &[&str]) -> (tempfile::TempDir, Keyring) {
+    let dir = tempfile::tempdir().unwrap();
+    let keyring = Keyring::new(dir.path());
+    for (i, name) in names.iter().enumerate() {
+      keyring.set(name, &Keystore::encrypt(&[i as u8], "password", kdf()).unwrap(), false).unwrap();
+    }
+    (dir, keyring)
+  }
+
+  #[test]
+  fn reports_missing_keystores() {
+    let (_dir, keyring) = keyring_with(&["a"]);
+    assert!(!keyring.exists("b"));
+    assert!(matches!(keyring.open("b"), Err(Error::NotFound(name)) if name == "b"));
+    assert!(matches!(keyring.get("b", "password"), Err(Error::NotFound(_))));
+    assert!(matches!(keyring.clone("b", "c", true), Err(Error::NotFound(_))));
+    assert!(!keyring.exists("c"));
+  }
+
+  #[test]
+  fn overwrites_only_when_asked() {
+    let (_dir, keyring) = keyring_with(&["a"]);
+    let replacement = Keystore::encrypt(&[42], "password", kdf()).unwrap();
+    assert!(matches!(keyring.set("a", &replacement, false), Err(Error::AlreadyExists(name)) if name == "a"));
+    assert_eq!(keyring.get("a", "password").unwrap(), vec![0]);
+    keyring.set("a", &replacement, true).unwrap();
+    assert_eq!(keyring.get("a", "password").unwrap(), vec![42]);
+  }
+
+  #[test]
+  fn clones_keystores() {
+    let (_dir, keyring) = keyring_with(&["a", "b"]);
+    keyring.clone("a", "c", false).unwrap();
+    assert_eq!(keyring.open("c").unwrap().to_bytes(), keyring.open("a").unwrap().to_bytes());
+    assert_eq!(keyring.get("c", "password").unwrap(), vec![0]);
+
+    assert!(matches!(keyring.clone("a", "b", false), Err(Error::AlreadyExists(name)) if name == "b"));
+    assert_eq!(keyring.get("b", "password").unwrap(), vec![1]);
+    keyring.clone("a", "b", true).unwrap();
+    assert_eq!(keyring.get("b", "password").unwrap(), vec![0]);
+  }
+
+  #[test]
+  fn lists_keystores() {
+    let (dir, keyring) = keyring_with(&["b", "a"]);
+    keyring.init_master("password", kdf()).unwrap();
+    fs::write(dir.path().join("a.0123456789abcdef.tmp"), b"interrupted").unwrap();
+    fs::write(dir.path().join(".hidden"), b"unrelated").unwrap();
+    assert!(dir.path().join(".master").exists());
+    assert_eq!(keyring.list().unwrap(), vec!["a", "b"]);
+    assert_eq!(keyring.list_with_errors().unwrap(), (vec!["a".to_string(), "b".to_string()], false));
+
+    let missing = Keyring::new(dir.path().join("missing"));
+    assert!(matches!(missing.list(), Err(Error::Io { .. })));
+  }
+
+  #[test]
+  fn init_master_wraps_all_keystores() {
+    let (_dir, keyring) = keyring_with(&["a", "b"]);
+    keyring.init_master("password", kdf()).unwrap();
+    assert!(keyring.has_master_key());
+    for (i, name) in ["a", "b"].iter().enumerate() {
+      assert!(keyring.open(name).unwrap().uses_master_key());
+      assert_eq!(keyring.get(name, "password").unwrap(), vec![i as u8]);
+    }
+    assert!(matches!(keyring.get("a", "wrong"), Err(Error::WrongPassword)));
+    assert_eq!(keyring.list().unwrap(), vec!["a", "b"]);
+  }
+
+  #[test]
+  fn init_master_requires_shared_password() {
+    let (_dir, keyring) = keyring_with(&["a"]);
+    keyring.set("b", &Keystore::encrypt(&[1], "other", kdf()).unwrap(), false).unwrap();
+    assert!(matches!(keyring.init_master("password", kdf()), Err(Error::WrongPassword)));
+    assert!(!keyring.has_master_key());
+    assert!(!keyring.open("a").unwrap().uses_master_key());
+  }
+
+  #[test]
+  fn resumes_interrupted_init_master() {
+    let (_dir, keyring) = keyring_with(&["a", "b"]);
+    // as if interrupted after writing the master key file, but before re-wrapping any keystore
+    let (master_file, _) = MasterFile::create("password", kdf()).unwrap();
+    keyring.write(&keyring.path(MASTER_FILE), &master_file.to_bytes()).unwrap();
+    let password = Credential::Password("password".into());
+    let Credential::MasterKey(master) = keyring.unlock(password.clone()).unwrap() else { unreachable!() };
+    keyring.set("a", &keyring.open("a").unwrap().wrap_with_master(&password, &master).unwrap(), true).unwrap();
+
+    assert!(matches!(keyring.init_master("wrong", kdf()), Err(Error::WrongPassword)));
+    keyring.init_master("password", kdf()).unwrap();
+    assert_eq!(fs::read(keyring.path(MASTER_FILE)).unwrap(), master_file.to_bytes());
+    for (i, name) in ["a", "b"].iter().enumerate() {
+      assert!(keyring.open(name).unwrap().uses_master_key());
+      assert_eq!(keyring.get(name, "password").unwrap(), vec![i as u8]);
+    }
+  }
+
+  #[test]
+  fn rekey_master_only_rewrites_master_file() {
+    let (_dir, keyring) = keyring_with(&["a", "b"]);
+    keyring.init_master("password", kdf()).unwrap();
+    let before = ["a", "b"].map(|name| fs::read(keyring.path(name)).unwrap());
+    let master_before = fs::read(keyring.path(MASTER_FILE)).unwrap();
+
+    assert!(matches!(keyring.rekey_master("wrong", "new", kdf()), Err(Error::WrongPassword)));
+    keyring.rekey_master("password", "new", kdf()).unwrap();
+    assert_ne!(fs::read(keyring.path(MASTER_FILE)).unwrap(), master_before);
+    assert_eq!(["a", "b"].map(|name| fs::read(keyring.path(name)).unwrap()), before);
+    assert_eq!(keyring.get("a", "new").unwrap(), vec![0]);
+    assert_eq!(keyring.get("b", "new").unwrap(), vec![1]);
+    assert!(matches!(keyring.get("a", "password"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn rekey_master_requires_master_key() {
+    let (_dir, keyring) = keyring_with(&["a"]);
+    assert!(matches!(keyring.rekey_master("password", "new", kdf()), Err(Error::NotFound(_))));
+  }
+
+  #[test]
+  fn unlock_for_falls_back_to_recovery_password() {
+    let (_dir, keyring) = keyring_with(&["a", "b"]);
+    keyring.init_master("password", kdf()).unwrap();
+    let keystore = keyring.open("a").unwrap();
+    let master = keyring.unlock_for(&keystore, Credential::Password("password".into())).unwrap();
+    assert!(matches!(master, Credential::MasterKey(_)));
+    keyring.set("a", &keystore.add_slot(&master, &Credential::Password("recovery".into()), kdf()).unwrap(), true).unwrap();
+
+    let keystore = keyring.open("a").unwrap();
+    let recovery = keyring.unlock_for(&keystore, Credential::Password("recovery".into())).unwrap();
+    assert!(matches!(&recovery, Credential::Password(password) if password == "recovery"));
+    assert_eq!(keyring.get("a", "recovery").unwrap(), vec![0]);
+    assert_eq!(keyring.get("a", "password").unwrap(), vec![0]);
+    assert!(matches!(keyring.get("b", "recovery"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn counts_reads() {
+    let (_dir, keyring) = keyring_with(&[]);
+    let policy = Policy { max_reads: Some(2), ..Policy::default() };
+    let keystore = Keystore::encrypt_with(&[1], "password", kdf(), Metadata { policy, ..Metadata::default() }).unwrap();
+    keyring.set("a", &keystore, false).unwrap();
+
+    assert!(matches!(keyring.get("a", "wrong"), Err(Error::WrongPassword)));
+    assert_eq!(keyring.get("a", "password").unwrap(), vec![1]);
+    assert_eq!(keyring.open("a").unwrap().metadata().policy.reads, 1);
+    assert_eq!(keyring.get("a", "password").unwrap(), vec![1]);
+    assert_eq!(keyring.open("a").unwrap().metadata().policy.reads, 2);
+    assert!(matches!(keyring.get("a", "password"), Err(Error::Denied(_))));
+    assert_eq!(keyring.open("a").unwrap().metadata().policy.reads, 2);
+  }
+
+  #[test]
+  fn refuses_expired_keystores() {
+    let (_dir, keyring) = keyring_with(&[]);
+    let policy = Policy { not_after: Some(1_700_000_000), ..Policy::default() };
+    let keystore = Keystore::encrypt_with(&[1], "password", kdf(), Metadata { policy, ..Metadata::default() }).unwrap();
+    keyring.set("a", &keystore, false).unwrap();
+    assert!(matches!(keyring.get("a", "password"), Err(Error::Denied(_))));
+  }
+
+  #[test]
+  fn writes_private_files_atomically() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secret");
+    fs::write(dir.path().join("secret.tmp"), b"unrelated").unwrap();
+
+    write_private(&path, b"first").unwrap();
+    write_private(&path, b"second").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"second");
+    assert_eq!(fs::read(dir.path().join("secret.tmp")).unwrap(), b"unrelated");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+  }
+}
@@ -0,0 +1,683 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::date::now;
+use crate::error::{Error, Result};
+use crate::format::{CipherKind, EnvelopeHeader, Format, FormatError, Header, Metadata, Slot, SALT_LEN};
+use crate::kdf::{derive_key_v1, Kdf, DEFAULT_LEGACY_ROUNDS};
+use crate::master::MasterKey;
+use crate::recipient::{Identity, Recipient};
+
+const KEYFILE_INFO: &[u8] = b"kayring/v3/keyfile";
+
+/// A single encrypted secret as stored on disk.
+#[derive(Clone, Debug)]
+pub struct Keystore {
+  format: Format,
+  legacy_rounds: u32,
+}
+
+/// Means of unlocking a keystore.
+#[derive(Clone, Debug)]
+pub enum Credential {
+  Password(String),
+  /// X25519 identities, any of which may be a recipient of the keystore.
+  Identities(Vec<Identity>),
+  /// Master key of the keyring, see [`crate::Keyring::unlock`].
+  MasterKey(MasterKey),
+  /// Contents of a keyfile. Unlike passwords, keyfiles are not stretched by a key derivation
+  /// function, so they must hold enough entropy on their own.
+  Keyfile(Vec<u8>),
+}
+
+impl Keystore {
+  /// Encrypt `value` into a new v2 keystore, using a fresh salt & nonce. Keystores are only upgraded
+  /// to v3 once they get [`Keystore::recipients`].
+  pub fn encrypt(value: &[u8], password: impl AsRef<str>, kdf: Kdf) -> Result<Self> {
+    Self::encrypt_with(value, password, kdf, Metadata::default())
+  }
+
+  /// Like [`Keystore::encrypt`], but records the given metadata alongside the secret.
+  pub fn encrypt_with(value: &[u8], password: impl AsRef<str>, kdf: Kdf, metadata: Metadata) -> Result<Self> {
+    Self::seal(value, password, kdf, now(), metadata)
+  }
+
+  /// Encrypt `value` into a new v3 keystore under a fresh data key wrapped under the master key of a
+  /// keyring.
+  pub fn encrypt_with_master(value: &[u8], master: &MasterKey, metadata: Metadata) -> Result<Self> {
+    let key = random_key();
+    Self::seal_envelope(value, &key, now(), metadata, vec![master.wrap_key(&key)?])
+  }
+
+  /// Re-encrypt `value`, the secret of this keystore, with the given metadata under a fresh salt &
+  /// nonce, retaining the key derivation settings & creation time. Legacy v1 keystores are upgraded.
+  pub fn reencrypt_with(&self, value: &[u8], password: impl AsRef<str>, metadata: Metadata) -> Result<Self> {
+    self.reencrypt(value, &Credential::Password(password.as_ref().to_string()), metadata)
+  }
+
+  /// Like [`Keystore::reencrypt_with`], but unlocks the keystore with any credential. v3 keystores
+  /// retain their data key & slots.
+  pub fn reencrypt(&self, value: &[u8], credential: &Credential, metadata: Metadata) -> Result<Self> {
+    match (&self.format, credential) {
+      (Format::V3 { header, slots, .. }, _) => {
+        let key = self.data_key(credential)?;
+        Self::seal_envelope(value, &key, header.created, metadata, slots.clone())
+      },
+      (_, Credential::Password(password)) => match self.header() {
+        Some(header) => Self::seal(value, password, header.kdf.clone(), header.created, metadata),
+        None => Self::encrypt_with(value, password, self.legacy_kdf(), metadata),
+      },
+      // only v3 keystores have recipients or a master key
+      _ => Err(Error::WrongPassword),
+    }
+  }
+
+  /// Change the password of this keystore, re-encrypting it with the given key derivation settings,
+  /// retaining its metadata & creation time. v3 keystores only re-wrap their data key in the slot of
+  /// the password, retaining their other slots.
+  pub fn rekey(&self, password: impl AsRef<str>, new_password: impl AsRef<str>, kdf: Kdf) -> Result<Self> {
+    let credential = Credential::Password(password.as_ref().to_string());
+    match &self.format {
+      Format::V3 { slots, .. } => {
+        let (index, key) = self.unlock_slot(&credential)?;
+        let mut slots = slots.clone();
+        slots[index] = password_slot(&key, new_password, kdf)?;
+        self.with_slots(slots)
+      },
+      _ => {
+        let created = self.header().map_or_else(now, |header| header.created);
+        Self::seal(&self.unlock(&credential)?, new_password, kdf, created, self.metadata())
+      },
+    }
+  }
+
+  /// X25519 recipients able to decrypt this keystore besides the password. Only v3 keystores have
+  /// any.
+  pub fn recipients(&self) -> Vec<Recipient> {
+    match &self.format {
+      Format::V3 { slots, .. } => slots.iter()
+        .filter_map(|slot| match slot {
+          Slot::X25519 { recipient, .. } => Some(Recipient::from_bytes(*recipient)),
+          _ => None,
+        })
+        .collect(),
+      _ => vec![],
+    }
+  }
+
+  /// Wrap the data key for another recipient. Older keystores are upgraded to v3 under a fresh data
+  /// key, retaining their password & key derivation settings.
+  pub fn add_recipient(&self, credential: &Credential, recipient: &Recipient) -> Result<Self> {
+    if self.recipients().contains(recipient) {
+      return Err(Error::InvalidValue(format!("{} is already a recipient", recipient)));
+    }
+
+    let keystore = self.upgrade(credential)?;
+    let key = keystore.data_key(credential)?;
+    let (ephemeral, wrapped_key) = recipient.wrap_key(&key)?;
+    let mut slots = keystore.slots().to_vec();
+    slots.push(Slot::X25519 { recipient: *recipient.as_bytes(), ephemeral, wrapped_key });
+    keystore.with_slots(slots)
+  }
+
+  /// Remove a recipient. The secret is re-encrypted under a fresh data key, so the recipient cannot
+  /// decrypt it anymore even if they kept the old data key. See [`Keystore::remove_slot`].
+  pub fn remove_recipient(&self, credentials: &[Credential], recipient: &Recipient) -> Result<Self> {
+    let index = self.slots().iter()
+      .position(|slot| matches!(slot, Slot::X25519 { recipient: bytes, .. } if bytes == recipient.as_bytes()))
+      .ok_or_else(|| Error::InvalidValue(format!("{} is not a recipient", recipient)))?;
+    self.remove_slot(credentials, index)
+  }
+
+  /// Wrap the data key for another password or keyfile in a new slot, without re-encrypting the
+  /// secret. Older keystores are upgraded to v3 under a fresh data key first, retaining their
+  /// password & key derivation settings.
+  pub fn add_slot(&self, credential: &Credential, new_credential: &Credential, kdf: Kdf) -> Result<Self> {
+    let keystore = self.upgrade(credential)?;
+    let key = keystore.data_key(credential)?;
+    let slot = match new_credential {
+      Credential::Password(password) => password_slot(&key, password, kdf)?,
+      Credential::Keyfile(keyfile) => keyfile_slot(&key, keyfile)?,
+      Credential::MasterKey(master) => master.wrap_key(&key)?,
+      Credential::Identities(_) => {
+        return Err(Error::InvalidValue("Identities are added as recipients".to_string()));
+      },
+    };
+    let mut slots = keystore.slots().to_vec();
+    slots.push(slot);
+    keystore.with_slots(slots)
+  }
+
+  /// Remove the slot at the given index. One of the credentials must unlock the keystore, & at least one
+  /// password or master key slot must remain. Identities cannot remove slots.
+  ///
+  /// Password, keyfile & master key slots are removed without re-encrypting the secret. Removing a
+  /// recipient rotates the data key instead, as its holder may have kept the old one: the secret is
+  /// re-encrypted under a fresh data key, which is wrapped for the remaining recipients & re-wrapped
+  /// in every other remaining slot with the credential unlocking it. Thus, the credentials must
+  /// unlock all remaining password, keyfile & master key slots.
+  pub fn remove_slot(&self, credentials: &[Credential], index: usize) -> Result<Self> {
+    // older keystores only have their password, like a single slot
+    let slots = self.slots();
+    if slots.len() <= 1 {
+      return Err(Error::InvalidValue("The last key slot cannot be removed".to_string()));
+    }
+    if index >= slots.len() {
+      return Err(Error::InvalidValue(format!("No key slot {}", index)));
+    }
+    if credentials.iter().any(|credential| matches!(credential, Credential::Identities(_))) {
+      return Err(Error::InvalidValue("Key slots cannot be removed with an identity".to_string()));
+    }
+    let remaining = slots.iter().enumerate().filter(|(i, _)| *i != index).map(|(_, slot)| slot);
+    if !remaining.clone().any(|slot| matches!(slot, Slot::Password { .. } | Slot::Master { .. })) {
+      return Err(Error::InvalidValue("The last password or master key slot cannot be removed".to_string()));
+    }
+
+    let (_, key) = credentials.iter()
+      .find_map(|credential| self.unlock_slot(credential).ok())
+      .ok_or(Error::WrongPassword)?;
+    if !matches!(slots[index], Slot::X25519 { .. }) {
+      return self.with_slots(remaining.cloned().collect());
+    }
+
+    let value = self.unlock_with_key(&key)?;
+    let new_key = random_key();
+    let mut new_slots = vec![];
+    for (i, slot) in slots.iter().enumerate().filter(|(i, _)| *i != index) {
+      let new_slot = match slot {
+        Slot::X25519 { recipient, .. } => {
+          let (ephemeral, wrapped_key) = Recipient::from_bytes(*recipient).wrap_key(&new_key)?;
+          Some(Slot::X25519 { recipient: *recipient, ephemeral, wrapped_key })
+        },
+        _ => rewrap_slot(slot, credentials, &new_key)?,
+      };
+      new_slots.push(new_slot.ok_or_else(|| {
+        Error::InvalidValue(format!("Key slot {} must be unlocked as well to re-wrap it under a fresh data key", i))
+      })?);
+    }
+    let Format::V3 { header, .. } = &self.format else {
+      return Err(Error::Other("Only v3 keystores have slots".to_string()));
+    };
+    Self::seal_envelope(&value, &new_key, header.created, header.metadata.clone(), new_slots)
+  }
+
+  /// Slots wrapping the data key of this v3 keystore. Older keystores have none, as their secret is
+  /// encrypted under the password directly.
+  pub fn slots(&self) -> &[Slot] {
+    match &self.format {
+      Format::V3 { slots, .. } => slots,
+      _ => &[],
+    }
+  }
+
+  /// Wrap the data key under the master key of a keyring instead of the given credential. Older
+  /// keystores are upgraded to v3 under a fresh data key. Other slots are retained.
+  pub fn wrap_with_master(&self, credential: &Credential, master: &MasterKey) -> Result<Self> {
+    match &self.format {
+      Format::V3 { slots, .. } => {
+        let (index, key) = self.unlock_slot(credential)?;
+        let mut slots = slots.clone();
+        slots[index] = master.wrap_key(&key)?;
+        self.with_slots(slots)
+      },
+      _ => {
+        let value = self.unlock(credential)?;
+        let key = random_key();
+        let created = self.header().map(|header| header.created).unwrap_or_else(now);
+        Self::seal_envelope(&value, &key, created, self.metadata(), vec![master.wrap_key(&key)?])
+      },
+    }
+  }
+
+  /// This keystore as v3 keystore. Older keystores are re-encrypted under a fresh data key, wrapped
+  /// under their password & key derivation settings.
+  fn upgrade(&self, credential: &Credential) -> Result<Self> {
+    if let Format::V3 { .. } = &self.format {
+      return Ok(self.clone());
+    }
+    let Credential::Password(password) = credential else {
+      return Err(Error::WrongPassword);
+    };
+    let value = self.unlock(credential)?;
+    let kdf = self.header().map(|header| header.kdf.clone()).unwrap_or_else(|| self.legacy_kdf());
+    let key = random_key();
+    let created = self.header().map(|header| header.created).unwrap_or_else(now);
+    let slots = vec![password_slot(&key, password, kdf)?];
+    Self::seal_envelope(&value, &key, created, self.metadata(), slots)
+  }
+
+  /// Whether the data key of this keystore is wrapped under the master key of its keyring.
+  pub fn uses_master_key(&self) -> bool {
+    self.envelope().is_some_and(|(_, slots)| slots.iter().any(|slot| matches!(slot, Slot::Master { .. })))
+  }
+
+  fn with_slots(&self, slots: Vec<Slot>) -> Result<Self> {
+    let Format::V3 { header, header_bytes, ciphertext, .. } = &self.format else {
+      return Err(Error::Other("Only v3 keystores have slots".to_string()));
+    };
+    Ok(Self {
+      format: Format::V3 {
+        header: header.clone(),
+        header_bytes: header_bytes.clone(),
+        slots,
+        ciphertext: ciphertext.clone(),
+      },
+      legacy_rounds: self.legacy_rounds,
+    })
+  }
+
+  fn seal_envelope(value: &[u8], key: &[u8; 32], created: u64, metadata: Metadata, slots: Vec<Slot>) -> Result<Self> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let header = EnvelopeHeader {
+      cipher: CipherKind::Aes256Gcm,
+      created,
+      nonce: nonce.into(),
+      metadata,
+    };
+    let header_bytes = header.to_bytes();
+
+    let ciphertext = match header.cipher {
+      CipherKind::Aes256Gcm => {
+        let cipher = Aes256Gcm::new(key.into());
+        cipher.encrypt(&header.nonce.into(), Payload { msg: value, aad: &header_bytes })
+          .map_err(|err| Error::Other(format!("Failed to encrypt: {}", err)))?
+      },
+    };
+
+    Ok(Self {
+      format: Format::V3 { header, header_bytes, slots, ciphertext },
+      legacy_rounds: DEFAULT_LEGACY_ROUNDS,
+    })
+  }
+
+  /// Unwrap the data key of a v3 keystore with the first matching slot.
+  fn data_key(&self, credential: &Credential) -> Result<[u8; 32]> {
+    self.unlock_slot(credential).map(|(_, key)| key)
+  }
+
+  /// Like [`Keystore::data_key`], but also returns the index of the matching slot.
+  fn unlock_slot(&self, credential: &Credential) -> Result<(usize, [u8; 32])> {
+    let Format::V3 { slots, .. } = &self.format else {
+      return Err(Error::Other("Only v3 keystores have a data key".to_string()));
+    };
+    for (index, slot) in slots.iter().enumerate() {
+      if let Some(key) = unwrap_slot(slot, credential)? {
+        return Ok((index, key));
+      }
+    }
+    Err(Error::WrongPassword)
+  }
+
+  /// Decrypt the secret of a v3 keystore with its data key.
+  fn unlock_with_key(&self, key: &[u8; 32]) -> Result<Vec<u8>> {
+    let Format::V3 { header, header_bytes, ciphertext, .. } = &self.format else {
+      return Err(Error::Other("Only v3 keystores have a data key".to_string()));
+    };
+    match header.cipher {
+      CipherKind::Aes256Gcm => {
+        let cipher = Aes256Gcm::new(key.into());
+        cipher.decrypt(&header.nonce.into(), Payload { msg: ciphertext, aad: header_bytes })
+          .map_err(|_| Error::WrongPassword)
+      },
+    }
+  }
+
+  fn legacy_kdf(&self) -> Kdf {
+    Kdf::Pbkdf2Sha256 { rounds: self.legacy_rounds }
+  }
+
+  fn seal(value: &[u8], password: impl AsRef<str>, kdf: Kdf, created: u64, metadata: Metadata) -> Result<Self> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let header = Header {
+      kdf,
+      cipher: CipherKind::Aes256Gcm,
+      created,
+      salt: salt.to_vec(),
+      nonce: nonce.into(),
+      metadata,
+    };
+    let header_bytes = header.to_bytes();
+    let key = header.kdf.derive(password, &header.salt)?;
+
+    let ciphertext = match header.cipher {
+      CipherKind::Aes256Gcm => {
+        let cipher = Aes256Gcm::new(&key.into());
+        cipher.encrypt(&header.nonce.into(), Payload { msg: value, aad: &header_bytes })
+          .map_err(|err| Error::Other(format!("Failed to encrypt: {}", err)))?
+      },
+    };
+
+    Ok(Self {
+      format: Format::V2 { header, header_bytes, ciphertext },
+      legacy_rounds: DEFAULT_LEGACY_ROUNDS,
+    })
+  }
+
+  /// Decrypt the secret stored in this keystore.
+  pub fn decrypt(&self, password: impl AsRef<str>) -> Result<Vec<u8>> {
+    self.unlock(&Credential::Password(password.as_ref().to_string()))
+  }
+
+  /// Decrypt the secret stored in this keystore with any credential. Only v3 keystores may be
+  /// unlocked with an identity.
+  pub fn unlock(&self, credential: &Credential) -> Result<Vec<u8>> {
+    match (&self.format, credential) {
+      (Format::V1 { salt, nonce, ciphertext }, Credential::Password(password)) => {
+        let key = derive_key_v1(password, salt, self.legacy_rounds);
+        let cipher = Aes256Gcm::new(&key.into());
+        cipher.decrypt(nonce.into(), ciphertext.as_ref())
+          .map_err(|_| Error::WrongPassword)
+      },
+      (Format::V2 { header, header_bytes, ciphertext }, Credential::Password(password)) => {
+        let key = header.kdf.derive(password, &header.salt)
+          .map_err(|err| FormatError::InvalidField("kdf", err.to_string()))?;
+        match header.cipher {
+          CipherKind::Aes256Gcm => {
+            let cipher = Aes256Gcm::new(&key.into());
+            cipher.decrypt(&header.nonce.into(), Payload { msg: ciphertext, aad: header_bytes })
+              .map_err(|_| Error::WrongPassword)
+          },
+        }
+      },
+      (Format::V3 { .. }, _) => self.unlock_with_key(&self.data_key(credential)?),
+      // only v3 keystores have recipients or a master key
+      _ => Err(Error::WrongPassword),
+    }
+  }
+
+  /// Set the number of PBKDF2 rounds used to decrypt legacy v1 keystores, which do not record it.
+  /// Has no effect on keystores of newer versions.
+  pub fn with_legacy_rounds(mut self, rounds: u32) -> Self {
+    self.legacy_rounds = rounds;
+    self
+  }
+
+  /// File format version of this keystore.
+  pub fn version(&self) -> u8 {
+    self.format.version()
+  }
+
+  /// Header describing how this v2 keystore was encrypted. Legacy v1 keystores have none, while v3
+  /// keystores have an [`Keystore::envelope`] instead.
+  pub fn header(&self) -> Option<&Header> {
+    match &self.format {
+      Format::V2 { header, .. } => Some(header),
+      _ => None,
+    }
+  }
+
+  /// Header of this v3 keystore, along with the slots wrapping its data key.
+  pub fn envelope(&self) -> Option<(&EnvelopeHeader, &[Slot])> {
+    match &self.format {
+      Format::V3 { header, slots, .. } => Some((header, slots)),
+      _ => None,
+    }
+  }
+
+  /// Metadata about the secret. Legacy v1 keystores always hold raw secrets.
+  pub fn metadata(&self) -> Metadata {
+    match &self.format {
+      Format::V1 { .. } => Metadata::default(),
+      Format::V2 { header, .. } => header.metadata.clone(),
+      Format::V3 { header, .. } => header.metadata.clone(),
+    }
+  }
+
+  /// Parse the contents of a keystore file. Never panics; malformed contents result in an
+  /// [`Error::Corrupt`] or [`Error::UnknownVersion`].
+  pub fn from_bytes(contents: &[u8]) -> Result<Self> {
+    Ok(Self {
+      format: Format::parse(contents)?,
+      legacy_rounds: DEFAULT_LEGACY_ROUNDS,
+    })
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    self.format.to_bytes()
+  }
+}
+
+pub(crate) fn random_key() -> [u8; 32] {
+  let mut key = [0u8; 32];
+  OsRng.fill_bytes(&mut key);
+  key
+}
+
+/// Wrap the data key under a key derived from the password with a fresh salt.
+pub(crate) fn password_slot(key: &[u8; 32], password: impl AsRef<str>, kdf: Kdf) -> Result<Slot> {
+  let mut salt = [0u8; SALT_LEN];
+  OsRng.fill_bytes(&mut salt);
+  let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+  let kek = kdf.derive(password, &salt)?;
+  let wrapped_key = Aes256Gcm::new(&kek.into()).encrypt(&nonce, key.as_slice())
+    .map_err(|err| Error::Other(format!("Failed to wrap key: {}", err)))?;
+  Ok(Slot::Password { kdf, salt: salt.to_vec(), nonce: nonce.into(), wrapped_key })
+}
+
+/// Wrap the data key under a key derived from the contents of a keyfile with a fresh salt.
+fn keyfile_slot(key: &[u8; 32], keyfile: &[u8]) -> Result<Slot> {
+  let mut salt = [0u8; SALT_LEN];
+  OsRng.fill_bytes(&mut salt);
+  let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+  let wrapped_key = Aes256Gcm::new(&keyfile_key(keyfile, &salt).into()).encrypt(&nonce, key.as_slice())
+    .map_err(|err| Error::Other(format!("Failed to wrap key: {}", err)))?;
+  Ok(Slot::Keyfile { salt: salt.to_vec(), nonce: nonce.into(), wrapped_key })
+}
+
+fn keyfile_key(keyfile: &[u8], salt: &[u8]) -> [u8; 32] {
+  let mut key = [0u8; 32];
+  Hkdf::<Sha256>::new(Some(salt), keyfile)
+    .expand(KEYFILE_INFO, &mut key)
+    .expect("32 bytes is a valid HKDF-SHA256 output length");
+  key
+}
+
+/// Unwrap the key wrapped by a password slot. Returns `None` if the password does not match.
+pub(crate) fn unwrap_password_slot(slot: &Slot, password: impl AsRef<str>) -> Result<Option<[u8; 32]>> {
+  let Slot::Password { kdf, salt, nonce, wrapped_key } = slot else {
+    return Ok(None);
+  };
+  let kek = kdf.derive(password, salt)
+    .map_err(|err| FormatError::InvalidField("kdf", err.to_string()))?;
+  Ok(Aes256Gcm::new(&kek.into()).decrypt(nonce.into(), wrapped_key.as_slice()).ok()
+    .and_then(|key| key.try_into().ok()))
+}
+
+/// Unwrap the data key in the slot with the credential. Returns `None` if the credential does not
+/// match the slot.
+fn unwrap_slot(slot: &Slot, credential: &Credential) -> Result<Option<[u8; 32]>> {
+  Ok(match (slot, credential) {
+    (Slot::Password { .. }, Credential::Password(password)) => unwrap_password_slot(slot, password)?,
+    (Slot::Keyfile { salt, nonce, wrapped_key }, Credential::Keyfile(keyfile)) => {
+      let kek = keyfile_key(keyfile, salt);
+      Aes256Gcm::new(&kek.into()).decrypt(nonce.into(), wrapped_key.as_slice()).ok()
+        .and_then(|key| key.try_into().ok())
+    },
+    (Slot::X25519 { recipient, ephemeral, wrapped_key }, Credential::Identities(identities)) => identities.iter()
+      .filter(|identity| identity.to_public().as_bytes() == recipient)
+      .find_map(|identity| identity.unwrap_key(ephemeral, wrapped_key)),
+    (Slot::Master { nonce, wrapped_key }, Credential::MasterKey(master)) => master.unwrap_key(nonce, wrapped_key),
+    _ => None,
+  })
+}
+
+/// Wrap a new data key in place of the slot with the first credential unlocking it, retaining its
+/// key derivation settings. Returns `None` if none does.
+fn rewrap_slot(slot: &Slot, credentials: &[Credential], key: &[u8; 32]) -> Result<Option<Slot>> {
+  for credential in credentials {
+    if unwrap_slot(slot, credential)?.is_none() {
+      continue;
+    }
+    return match (slot, credential) {
+      (Slot::Password { kdf, .. }, Credential::Password(password)) => password_slot(key, password, kdf.clone()).map(Some),
+      (_, Credential::Keyfile(keyfile)) => keyfile_slot(key, keyfile).map(Some),
+      (_, Credential::MasterKey(master)) => master.wrap_key(key).map(Some),
+      _ => Ok(None),
+    };
+  }
+  Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::encoding::Encoding;
+  use crate::format::Policy;
+
+  const VALUE: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+  // written independently of kayring: PBKDF2-HMAC-SHA256 with 100,000 rounds of `hunter2` & AES-256-GCM
+  // without associated data, as by versions of kayring before the v2 format
+  const V1: &str = "01000102030405060708090a0b0c0d0e0f101112131415161718191a1b2121938259abc511aa03de267c3458dfc8fbd38a3105e52af6c98fd0d4b01e4663bb5c309f8b3c068bf162858f2288a2";
+
+  fn kdf() -> Kdf {
+    Kdf::Pbkdf2Sha256 { rounds: 1000 }
+  }
+
+  fn password(password: &str) -> Credential {
+    Credential::Password(password.to_string())
+  }
+
+  fn v3() -> Keystore {
+    let value = hex::decode(VALUE).unwrap();
+    Keystore::encrypt(&value, "operator", kdf()).unwrap()
+      .add_slot(&password("operator"), &password("recovery"), kdf()).unwrap()
+  }
+
+  #[test]
+  fn reads_v1() {
+    let keystore = Keystore::from_bytes(&hex::decode(V1).unwrap()).unwrap();
+    assert_eq!(keystore.version(), 1);
+    assert_eq!(hex::encode(keystore.decrypt("hunter2").unwrap()), VALUE);
+    assert!(matches!(keystore.decrypt("hunter3"), Err(Error::WrongPassword)));
+    assert!(matches!(keystore.with_legacy_rounds(1000).decrypt("hunter2"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn upgrades_v1() {
+    let keystore = Keystore::from_bytes(&hex::decode(V1).unwrap()).unwrap();
+    let value = keystore.decrypt("hunter2").unwrap();
+    let rekeyed = keystore.rekey("hunter2", "hunter3", kdf()).unwrap();
+    assert_eq!(rekeyed.version(), 2);
+    assert_eq!(rekeyed.decrypt("hunter3").unwrap(), value);
+  }
+
+  #[test]
+  fn rekeys_v2_retaining_metadata() {
+    let metadata = Metadata {
+      encoding: Encoding::Utf8,
+      policy: Policy { max_reads: Some(3), reads: 1, ..Policy::default() },
+      ..Metadata::default()
+    };
+    let keystore = Keystore::seal(b"token", "hunter2", kdf(), 1_700_000_000, metadata.clone()).unwrap();
+    let rekeyed = keystore.rekey("hunter2", "hunter3", Kdf::Pbkdf2Sha256 { rounds: 2000 }).unwrap();
+    let header = rekeyed.header().unwrap();
+    assert_eq!(header.created, 1_700_000_000);
+    assert_eq!(header.kdf, Kdf::Pbkdf2Sha256 { rounds: 2000 });
+    assert_eq!(rekeyed.metadata(), metadata);
+    assert_eq!(rekeyed.decrypt("hunter3").unwrap(), b"token");
+    assert!(matches!(keystore.rekey("hunter3", "hunter4", kdf()), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn round_trips_v2() {
+    let value = hex::decode(VALUE).unwrap();
+    let keystore = Keystore::encrypt(&value, "hunter2", kdf()).unwrap();
+    let bytes = keystore.to_bytes();
+    let parsed = Keystore::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed.version(), 2);
+    assert_eq!(parsed.to_bytes(), bytes);
+    assert_eq!(parsed.decrypt("hunter2").unwrap(), value);
+    assert!(matches!(parsed.decrypt("hunter3"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn authenticates_header() {
+    let keystore = Keystore::encrypt(&hex::decode(VALUE).unwrap(), "hunter2", kdf()).unwrap();
+    let mut bytes = keystore.to_bytes();
+    // flip a bit of the creation time, which is not needed for decryption
+    let created = bytes.len() - 48 - 12 - 3 - 16 - 3 - 8;
+    bytes[created] ^= 1;
+    let tampered = Keystore::from_bytes(&bytes).unwrap();
+    assert!(matches!(tampered.decrypt("hunter2"), Err(Error::WrongPassword)));
+  }
+
+  #[test]
+  fn adds_slots_without_reencrypting() {
+    let keystore = v3();
+    assert_eq!(keystore.version(), 3);
+    assert_eq!(keystore.slots().len(), 2);
+    let value = keystore.decrypt("operator").unwrap();
+    assert_eq!(keystore.decrypt("recovery").unwrap(), value);
+
+    let keyfile = Credential::Keyfile(vec![42; 32]);
+    let added = keystore.add_slot(&password("recovery"), &keyfile, kdf()).unwrap();
+    assert_eq!(added.unlock(&keyfile).unwrap(), value);
+    let Format::V3 { ciphertext: before, .. } = &keystore.format else { unreachable!() };
+    let Format::V3 { ciphertext: after, .. } = &added.format else { unreachable!() };
+    assert_eq!(before, after);
+
+    let parsed = Keystore::from_bytes(&added.to_bytes()).unwrap();
+    assert_eq!(parsed.unlock(&keyfile).unwrap(), value);
+  }
+
+  #[test]
+  fn removes_slots() {
+    let keystore = v3();
+    let removed = keystore.remove_slot(&[password("recovery")], 0).unwrap();
+    assert_eq!(removed.slots().len(), 1);
+    assert!(matches!(removed.decrypt("operator"), Err(Error::WrongPassword)));
+    assert_eq!(hex::encode(removed.decrypt("recovery").unwrap()), VALUE);
+
+    assert!(matches!(keystore.remove_slot(&[password("wrong")], 0), Err(Error::WrongPassword)));
+    assert!(matches!(keystore.remove_slot(&[password("operator")], 2), Err(Error::InvalidValue(_))));
+    assert!(matches!(removed.remove_slot(&[password("recovery")], 0), Err(Error::InvalidValue(_))));
+  }
+
+  #[test]
+  fn keeps_a_password_slot() {
+    let keyfile = Credential::Keyfile(vec![42; 32]);
+    let keystore = Keystore::encrypt(&hex::decode(VALUE).unwrap(), "operator", kdf()).unwrap()
+      .add_slot(&password("operator"), &keyfile, kdf()).unwrap();
+    assert!(matches!(keystore.remove_slot(std::slice::from_ref(&keyfile), 0), Err(Error::InvalidValue(_))));
+    assert_eq!(keystore.remove_slot(&[keyfile], 1).unwrap().slots().len(), 1);
+  }
+
+  #[test]
+  fn rotates_data_key_when_removing_recipient() {
+    let identity = Identity::generate();
+    let other = Identity::generate();
+    let keystore = v3()
+      .add_recipient(&password("operator"), &identity.to_public()).unwrap()
+      .add_recipient(&password("operator"), &other.to_public()).unwrap();
+    let old_key = keystore.data_key(&Credential::Identities(vec![identity.clone()])).unwrap();
+
+    // the recovery slot cannot be re-wrapped without its password
+    let recipient = identity.to_public();
+    assert!(matches!(keystore.remove_recipient(&[password("operator")], &recipient), Err(Error::InvalidValue(_))));
+    assert!(matches!(
+      keystore.remove_recipient(&[Credential::Identities(vec![other.clone()])], &recipient),
+      Err(Error::InvalidValue(_)),
+    ));
+
+    let removed = keystore.remove_recipient(&[password("operator"), password("recovery")], &recipient).unwrap();
+    assert_eq!(removed.recipients(), vec![other.to_public()]);
+    assert!(matches!(removed.unlock(&Credential::Identities(vec![identity])), Err(Error::WrongPassword)));
+    assert!(removed.unlock_with_key(&old_key).is_err());
+    for credential in [password("operator"), password("recovery"), Credential::Identities(vec![other])] {
+      assert_eq!(hex::encode(removed.unlock(&credential).unwrap()), VALUE);
+    }
+  }
+
+  #[test]
+  fn rejects_unknown_versions() {
+    assert!(matches!(Keystore::from_bytes(&[9, 0]), Err(Error::UnknownVersion(9))));
+    assert!(matches!(Keystore::from_bytes(&[]), Err(Error::Corrupt { .. })));
+  }
+}
@@ -0,0 +1,46 @@
+//! Kayring - Kiru's Keyring
+//!
+//! A simple encrypted keyring for cryptocurrency private keys. A [`Keyring`] is a directory of
+//! [`Keystore`]s, each holding a single secret encrypted under a password.
+//!
+//! ```no_run
+//! use kayring::{Kdf, Keyring, Keystore};
+//!
+//! let keyring = Keyring::open_default()?;
+//! let keystore = Keystore::encrypt(&[0xde, 0xad, 0xbe, 0xef], "password", Kdf::default())?;
+//! keyring.set("deployer", &keystore, false)?;
+//! assert_eq!(keyring.get("deployer", "password")?, vec![0xde, 0xad, 0xbe, 0xef]);
+//! # Ok::<(), kayring::Error>(())
+//! ```
+
+pub mod address;
+pub mod age_file;
+#[cfg(unix)]
+pub mod agent;
+pub mod cosmos;
+pub mod eip712;
+pub mod eth;
+pub mod eth_keystore;
+pub mod eth_tx;
+pub mod hd;
+pub mod recipient;
+pub mod signer;
+pub mod template;
+
+mod date;
+mod encoding;
+mod error;
+mod format;
+mod kdf;
+mod keyring;
+mod keystore;
+mod master;
+mod rlp;
+
+pub use encoding::Encoding;
+pub use error::{Error, Result};
+pub use format::{CipherKind, EnvelopeHeader, FormatError, Header, Metadata, Policy, SecretKind, Slot};
+pub use kdf::{Kdf, DEFAULT_LEGACY_ROUNDS};
+pub use keyring::{write_private, Keyring};
+pub use keystore::{Credential, Keystore};
+pub use master::MasterKey;
@@ -1,14 +1,31 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-
-use aes_gcm::aead::rand_core::RngCore;
-use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
-use aes_gcm::aead::{Aead, OsRng};
-use clap::{Args, Parser, Subcommand};
-use pbkdf2::hmac::Hmac;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+#[cfg(unix)]
+use kayring::agent::{Agent, AgentClient};
+use kayring::address::Chain;
+use kayring::age_file;
+use kayring::cosmos::SignDoc;
+use kayring::eip712::TypedData;
+use kayring::eth_tx::Transaction;
+use kayring::recipient::{Identity, Recipient};
+use kayring::signer::Signer;
+use kayring::template::{Filter, Template};
+use kayring::{
+  eth, eth_keystore, hd, write_private, Credential, Encoding, Error, Kdf, Keyring, Keystore, Metadata, Policy, Result, SecretKind,
+  Slot,
+};
 use rpassword::read_password;
-use sha2::Sha256;
-use unicode_normalization::UnicodeNormalization;
+
+/// Environment variables holding secrets, which are not passed on to commands run by `exec`.
+const SECRET_ENV_VARS: [&str; 5] = [
+  "KAYRING_PASSWORD",
+  "KAYRING_NEW_PASSWORD",
+  "KAYRING_FILE_PASSWORD",
+  "KAYRING_VALUE",
+  "KAYRING_MNEMONIC_PASSPHRASE",
+];
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -22,8 +39,32 @@ struct Cli {
 enum Commands {
   Set(SetArgs),
   Get(GetArgs),
+  Exec(ExecArgs),
   List(ListArgs),
   Clone(CloneArgs),
+  Verify(VerifyArgs),
+  #[command(alias = "rekey")]
+  Passwd(PasswdArgs),
+  Import(ImportArgs),
+  Export(ExportArgs),
+  Address(AddressArgs),
+  SignTx(SignTxArgs),
+  SignMessage(SignMessageArgs),
+  SignTypedData(SignArgs),
+  SignCosmos(SignArgs),
+  ServeSigner(ServeSignerArgs),
+  Render(RenderArgs),
+  Policy(PolicyCmdArgs),
+  Recipients(RecipientsArgs),
+  Slot(SlotArgs),
+  Keygen(KeygenArgs),
+  InitMaster(InitMasterArgs),
+  #[cfg(unix)]
+  Agent(AgentArgs),
+  #[cfg(unix)]
+  Unlock(UnlockArgs),
+  #[cfg(unix)]
+  Lock(LockArgs),
 }
 
 #[derive(Args, Debug)]
@@ -35,9 +76,46 @@ struct SetArgs {
   #[arg(long, env = "KAYRING_VALUE")]
   value: Option<String>,
 
+  /// Read `value` from stdin. A single trailing newline is ignored.
+  #[arg(long, conflicts_with_all = ["value_file", "from_file"])]
+  value_stdin: bool,
+
+  /// Read `value` from the given file, e.g. on a tmpfs. A single trailing newline is ignored.
+  #[arg(long, conflicts_with = "from_file")]
+  value_file: Option<String>,
+
+  /// Representation of `value`: `hex` (`0x` prefixed), `utf8`, `base64`, `base58`, or `file` to store the contents of
+  /// the file at the path given as `value`. Recorded in the keystore, so `get` reproduces the value as given.
+  #[arg(long, default_value = "hex", conflicts_with = "mnemonic")]
+  encoding: Encoding,
+
+  /// Store the contents of the given file, e.g. a binary keyfile. Shorthand for `--encoding file --value <FROM_FILE>`.
+  #[arg(long, conflicts_with_all = ["encoding", "mnemonic"])]
+  from_file: Option<String>,
+
+  /// Treat `value` as a BIP-39 mnemonic. Its seed is stored instead, from which keys are derived with `get --path`.
+  #[arg(short = 'm', long)]
+  mnemonic: bool,
+
+  /// Optional BIP-39 passphrase of the mnemonic.
+  #[arg(long, env = "KAYRING_MNEMONIC_PASSPHRASE", requires = "mnemonic")]
+  mnemonic_passphrase: Option<String>,
+
+  /// Read the BIP-39 passphrase from the first line of the given file. Takes precedence over `mnemonic_passphrase`.
+  #[arg(long, requires = "mnemonic")]
+  mnemonic_passphrase_file: Option<String>,
+
+  /// Read the BIP-39 passphrase from the first line of the given file descriptor. Takes precedence over
+  /// `mnemonic_passphrase`.
+  #[arg(long, requires = "mnemonic", conflicts_with = "mnemonic_passphrase_file")]
+  mnemonic_passphrase_fd: Option<u32>,
+
   #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
   password: Option<String>,
 
+  #[command(flatten)]
+  password_input: PasswordInput,
+
   /// Do not output logs or prompt for input. `value` becomes required.
   #[arg(short = 's', long)]
   silent: bool,
@@ -54,9 +132,101 @@ struct SetArgs {
   #[arg(long, env = "KAYRING_DIR")]
   dir: Option<String>,
 
-  /// Number of rounds to derive the encryption key. Remember this number as it is needed to retrieve the key again!
+  #[command(flatten)]
+  kdf: KdfArgs,
+
+  #[command(flatten)]
+  policy: PolicyArgs,
+}
+
+#[derive(Args, Debug)]
+struct PasswordInput {
+  /// Read the encryption password from the first line of the given file, e.g. on a tmpfs. Takes precedence over `password`.
+  #[arg(long)]
+  password_file: Option<String>,
+
+  /// Read the encryption password from the first line of the given file descriptor, e.g. a pipe. Takes precedence over `password`.
+  #[arg(long, conflicts_with = "password_file")]
+  password_fd: Option<u32>,
+
+  /// Shell command printing the encryption password on the first line of its stdout, used unless a `password` is given.
+  /// The names of the keystores are passed in `KAYRING_KEYSTORE`, separated by commas.
+  #[arg(long, env = "KAYRING_PASSWORD_COMMAND")]
+  password_command: Option<String>,
+}
+
+// Credentials unlocking existing keystores: a password, or an identity or keyfile instead.
+#[derive(Args, Debug)]
+struct CredentialInput {
+  #[command(flatten)]
+  password_input: PasswordInput,
+
+  /// Unlock existing keystores with the X25519 identities of the given age identity file instead of a password. Only
+  /// works for keystores which have the identity as recipient.
+  #[arg(long, env = "KAYRING_IDENTITY")]
+  identity: Option<String>,
+
+  /// Unlock existing keystores with the contents of the given keyfile instead of a password. Only works for keystores
+  /// with a slot for the keyfile.
+  #[arg(long, env = "KAYRING_KEYFILE", conflicts_with = "identity")]
+  keyfile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct PolicyArgs {
+  /// Refuse to reveal the secret after this date: `YYYY-MM-DD` (end of that day in UTC), `YYYY-MM-DDTHH:MM:SSZ` or a unix timestamp.
+  #[arg(long, value_parser = parse_date)]
+  not_after: Option<u64>,
+
+  /// Refuse to reveal the secret after it has been read this many times.
+  #[arg(long)]
+  max_reads: Option<u32>,
+
+  /// Ask for confirmation each time the secret is revealed. The secret can then not be revealed in `--silent` mode.
+  #[arg(long)]
+  confirm: bool,
+
+  /// Refuse to reveal the secret in `--silent` mode.
+  #[arg(long)]
+  refuse_silent: bool,
+}
+
+#[derive(Args, Debug)]
+struct KdfArgs {
+  /// Key derivation function used to derive the encryption key from the password. Recorded in the keystore.
+  #[arg(long, value_enum, default_value_t = KdfKind::Argon2id, env = "KAYRING_KDF")]
+  kdf: KdfKind,
+
+  /// Number of PBKDF2 rounds to derive the encryption key. Recorded in the keystore.
   #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
   derivation_rounds: u32,
+
+  /// Argon2id memory cost in KiB. Recorded in the keystore.
+  #[arg(long, default_value_t = argon2::Params::DEFAULT_M_COST, env = "KAYRING_MEMORY_COST")]
+  memory_cost: u32,
+
+  /// Argon2id number of iterations. Recorded in the keystore.
+  #[arg(long, default_value_t = argon2::Params::DEFAULT_T_COST, env = "KAYRING_TIME_COST")]
+  time_cost: u32,
+
+  /// Argon2id degree of parallelism, or scrypt parallelization parameter `p`. Recorded in the keystore.
+  #[arg(long, default_value = "1", env = "KAYRING_PARALLELISM")]
+  parallelism: u32,
+
+  /// scrypt CPU/memory cost parameter as log2 of `N`. Recorded in the keystore.
+  #[arg(long, default_value_t = scrypt::Params::RECOMMENDED_LOG_N, env = "KAYRING_LOG_N")]
+  log_n: u8,
+
+  /// scrypt block size parameter `r`. Recorded in the keystore.
+  #[arg(long, default_value_t = scrypt::Params::RECOMMENDED_R, env = "KAYRING_BLOCK_SIZE")]
+  block_size: u32,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum KdfKind {
+  Argon2id,
+  Scrypt,
+  Pbkdf2,
 }
 
 #[derive(Args, Debug)]
@@ -68,6 +238,9 @@ struct GetArgs {
   #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
   password: Option<String>,
 
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
   /// Do not output logs or prompt for input.
   #[arg(short = 's', long)]
   silent: bool,
@@ -76,9 +249,22 @@ struct GetArgs {
   #[arg(long, env = "KAYRING_DIR")]
   dir: Option<String>,
 
-  /// Number of rounds to derive the encryption key. This must match the same amount used to set the key!
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
   #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
   derivation_rounds: u32,
+
+  /// For mnemonic keystores, output the child private key at this BIP-32 derivation path, e.g. `m/44'/60'/0'/0/0`,
+  /// or of a chain preset: `eth`, `cosmos`, `solana` or `bitcoin`. Custom paths use secp256k1.
+  #[arg(long)]
+  path: Option<String>,
+
+  /// Address index of the chain preset given as `path`.
+  #[arg(long, default_value = "0", requires = "path")]
+  index: u32,
+
+  /// Write the value to the given file with permissions 0600 instead of printing it.
+  #[arg(long)]
+  to_file: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -105,224 +291,1929 @@ struct CloneArgs {
   dir: Option<String>,
 }
 
-fn main() {
-  let cli = Cli::parse();
+#[derive(Args, Debug)]
+struct VerifyArgs {
+  /// Names of the keystores to verify.
+  #[arg(required_unless_present = "all", conflicts_with = "all")]
+  names: Vec<String>,
 
-  let res = match cli.command {
-    Commands::Set(args) => sub_set(args),
-    Commands::Get(args) => sub_get(args),
-    Commands::List(args) => sub_list(args),
-    Commands::Clone(args) => sub_clone(args),
-  };
-  if let Err(e) = res {
-    eprintln!("{}", e);
-    std::process::exit(1);
-  }
+  /// Verify all keystores in the directory. They must share the same password.
+  #[arg(short = 'a', long)]
+  all: bool,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input. Only the exit code reports the result.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
 }
 
-fn sub_set(args: SetArgs) -> Result<(), String> {
-  let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-  let mut salt = [0u8; 16];
-  OsRng.fill_bytes(&mut salt);
-  assert!(nonce.len() == 12, "Unexpected nonce length");
+#[derive(Args, Debug)]
+struct PasswdArgs {
+  /// Names of the keystores to re-encrypt. They must share the same password.
+  #[arg(required_unless_present = "all", conflicts_with = "all")]
+  names: Vec<String>,
 
-  let dirpath = rootdir(args.dir)?;
-  let filepath = dirpath.join(&args.name);
+  /// Re-encrypt all keystores in the directory. They must share the same password.
+  #[arg(short = 'a', long)]
+  all: bool,
 
-  if filepath.exists() && !args.force {
-    return Err(format!("A kaystore {} already exists. Use --force to overwrite.", args.name));
-  }
+  /// Current encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
 
-  let password = args.password.ok_or(())
-    .or_else(|_| {
-      if args.silent {
-        Ok("".to_string())
-      } else {
-        let pw = promptpw("Enter password:");
-        let pw2 = promptpw("Confirm password:");
-        if pw != pw2 {
-          Err("Passwords do not match".to_string())
-        } else {
-          Ok(pw)
-        }
-      }
-    })?;
+  #[command(flatten)]
+  password_input: PasswordInput,
 
-  let privkey = args.value.ok_or(())
-    .or_else(|_| {
-      if args.silent {
-        return Err("Value is required in silent mode".to_string());
-      }
-      let value = promptpw("Enter value:");
-      if !value.starts_with("0x") {
-        return Err("Value must be a hex string starting with '0x'".to_string());
-      }
-      Ok(value)
-    })?;
-  let value = hex::decode(&privkey[2..])
-    .map_err(|_| format!("Value must be a valid hex string"))?;
+  /// New encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(long, env = "KAYRING_NEW_PASSWORD")]
+  new_password: Option<String>,
 
-  if !args.silent {
-    println!("Encrypting...");
-  }
+  /// Read the new encryption password from the first line of the given file.
+  #[arg(long)]
+  new_password_file: Option<String>,
 
-  let key = derive_key_v1(password, salt.as_ref(), args.derivation_rounds);
-  let cipher = Aes256Gcm::new(&key.into());
+  /// Read the new encryption password from the first line of the given file descriptor.
+  #[arg(long, conflicts_with = "new_password_file")]
+  new_password_fd: Option<u32>,
 
-  let encrypted = cipher.encrypt(&nonce, value.as_ref())
-    .map_err(|err| format!("Failed to encrypt: {}", err))?;
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
 
-  let contents: Vec<u8> = [
-    vec![1u8], // file version 1
-    salt.to_vec(),
-    nonce.to_vec(),
-    encrypted.to_vec()
-  ].concat();
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
 
-  fs::create_dir_all(dirpath.clone())
-    .map_err(|err| {
-      format!("Failed to create the directory at {}: {}", dirpath.to_string_lossy(), err)
-    })?;
+  /// Number of rounds the current encryption key was derived with. Only used for legacy v1 keystores, which do not record it.
+  #[arg(long, default_value = "100000", env = "KAYRING_OLD_DERIVATION_ROUNDS")]
+  old_derivation_rounds: u32,
 
-  fs::write(filepath.clone(), contents)
-    .map_err(|err| {
-      format!("Could not write to file {}: {}", filepath.to_string_lossy(), err)
-    })?;
+  #[command(flatten)]
+  kdf: KdfArgs,
+}
 
-  if args.echo {
-    println!("{}", privkey);
-  }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FileFormat {
+  /// Ethereum Web3 Secret Storage, i.e. keystore v3 JSON as used by geth, Foundry & MetaMask.
+  EthKeystore,
+  /// age encrypted file holding the value in its encoding, encrypted with a passphrase or to X25519 recipients.
+  Age,
+}
 
-  Ok(())
+#[derive(Args, Debug)]
+struct ImportArgs {
+  /// Name of the keystore to create.
+  name: String,
+
+  /// Path of the file to import.
+  file: String,
+
+  /// Format of the file to import.
+  #[arg(long, value_enum)]
+  format: FileFormat,
+
+  /// Password of the file to import. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(long, env = "KAYRING_FILE_PASSWORD")]
+  file_password: Option<String>,
+
+  /// Read the password of the file from the first line of the given file. Takes precedence over `file_password`.
+  #[arg(long)]
+  file_password_file: Option<String>,
+
+  /// Read the password of the file from the first line of the given file descriptor. Takes precedence over
+  /// `file_password`.
+  #[arg(long, conflicts_with = "file_password_file")]
+  file_password_fd: Option<u32>,
+
+  /// Decrypt age files encrypted to recipients with the X25519 identities of the given age identity file.
+  #[arg(long, env = "KAYRING_FILE_IDENTITY")]
+  file_identity: Option<String>,
+
+  /// Representation of the value held by age files, as for `set`. A single trailing newline is ignored unless `file`.
+  #[arg(long, default_value = "hex")]
+  encoding: Encoding,
+
+  /// Encryption password of the new keystore. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  password_input: PasswordInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Overwrite the key if it already exists.
+  #[arg(short = 'f', long)]
+  force: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  #[command(flatten)]
+  kdf: KdfArgs,
+
+  #[command(flatten)]
+  policy: PolicyArgs,
 }
 
-fn sub_get(args: GetArgs) -> Result<(), String> {
-  let dirpath = rootdir(args.dir)?;
-  let filepath = dirpath.join(&args.name);
+#[derive(Args, Debug)]
+struct ExportArgs {
+  /// Name of the keystore to export.
+  name: String,
 
-  if !filepath.exists() {
-    return Err(format!("No kaystore found for {}", args.name));
-  }
+  /// Format to export to.
+  #[arg(long, value_enum)]
+  format: FileFormat,
 
-  let password = args.password.ok_or(())
-    .or_else(|_| -> Result<String, String> {
-      if args.silent {
-        Ok("".to_string())
-      } else {
-        Ok(promptpw("Enter password:"))
-      }
-    })?;
+  /// Path of the file to write, replaced atomically & only accessible by the current user. If omitted, the export is
+  /// printed to stdout.
+  #[arg(short = 'o', long)]
+  output: Option<String>,
 
-  let contents = fs::read(filepath.clone())
-    .map_err(|err| {
-      format!("Could not read from file {}: {}", filepath.to_string_lossy(), err)
-    })?;
+  /// Password to encrypt the exported file with. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(long, env = "KAYRING_FILE_PASSWORD")]
+  file_password: Option<String>,
 
-  let filever = contents[0];
-  let (key, nonce, encrypted) = match filever {
-    1 => (derive_key_v1(password, &contents[1..17], args.derivation_rounds), &contents[17..29], &contents[29..]),
-    _ => return Err("Unknown file version".to_string()),
-  };
+  /// Read the password of the exported file from the first line of the given file. Takes precedence over
+  /// `file_password`.
+  #[arg(long)]
+  file_password_file: Option<String>,
 
-  let cipher = Aes256Gcm::new(&key.into());
-  let cleartext = cipher.decrypt(nonce.into(), encrypted)
-    .map_err(|err| format!("Failed to decrypt: {}", err))?;
-  let cleartext = hex::encode(cleartext);
+  /// Read the password of the exported file from the first line of the given file descriptor. Takes precedence over
+  /// `file_password`.
+  #[arg(long, conflicts_with = "file_password_file")]
+  file_password_fd: Option<u32>,
 
-  println!("0x{}", cleartext);
+  /// Use cheaper scrypt parameters for eth-keystore exports, akin to geth's `--lightkdf`.
+  #[arg(long)]
+  light: bool,
 
-  Ok(())
+  /// Encrypt age exports to the given X25519 recipient, e.g. `age1...`, instead of a file password. May be repeated.
+  #[arg(long = "recipient", value_name = "RECIPIENT")]
+  recipients: Vec<Recipient>,
+
+  /// PEM encode age exports, as done by `age --armor`.
+  #[arg(long)]
+  armor: bool,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
 }
 
-fn sub_list(args: ListArgs) -> Result<(), String> {
-  let dirpath = rootdir(args.dir)?;
-  let entries = fs::read_dir(dirpath.clone())
-    .map_err(|err| {
-      format!("Could not read from directory {}: {}", dirpath.to_string_lossy(), err)
-    })?;
+#[derive(Args, Debug)]
+struct AddressArgs {
+  /// Name of the key to show the address of.
+  name: String,
 
-  let mut has_errs = false;
-  let mut results: Vec<String> = entries
-    .map(|entry| -> Option<String> {
-      match entry {
-        Ok(entry) => Some(entry.file_name().to_string_lossy().to_string()),
-        Err(_) => {
-          has_errs = true;
-          None
-        },
-      }
-    })
-    .filter(|entry| entry.is_some())
-    .map(|entry| entry.unwrap())
-    .collect();
-  results.sort();
+  /// Chain to show the address for: `eth`, `cosmos:<prefix>`, `solana` or `bitcoin`.
+  #[arg(long, default_value = "eth")]
+  chain: Chain,
 
-  println!("{}", results.join(", "));
+  /// Only print the address.
+  #[arg(long)]
+  short: bool,
 
-  if has_errs {
-    eprintln!("Some entries could not be read.");
-  }
+  #[command(flatten)]
+  derive: DeriveArgs,
 
-  Ok(())
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
 }
 
-fn sub_clone(args: CloneArgs) -> Result<(), String> {
-  let dirpath = rootdir(args.dir)?;
-  let frompath = dirpath.join(&args.from);
-  let topath = dirpath.join(&args.to);
+#[derive(Args, Debug)]
+struct SignArgs {
+  /// Name of the key to sign with.
+  name: String,
 
-  if !frompath.exists() {
-    return Err(format!("No kaystore found for {}", args.from));
-  }
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
 
-  if topath.exists() && !args.force {
-    return Err(format!("A kaystore {} already exists. Use --force to overwrite.", args.to));
-  }
+  #[command(flatten)]
+  credential_input: CredentialInput,
 
-  fs::copy(frompath.clone(), topath.clone())
-    .map_err(|err| {
-      format!("Could not copy from {} to {}: {}", frompath.to_string_lossy(), topath.to_string_lossy(), err)
-    })?;
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
 
-  Ok(())
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+
+  #[command(flatten)]
+  derive: DeriveArgs,
 }
 
-fn rootdir(dir: Option<String>) -> Result<PathBuf, String> {
-  dir
-    .or_else(|| {
-      let homedir = home::home_dir()?;
-      Some(format!("{}/.kayring", homedir.to_string_lossy()))
-    })
-    .map(|path| Path::new(&path).to_owned())
-    .ok_or_else(|| "Could not determine the root directory".to_string())
+#[derive(Args, Debug)]
+struct SignTxArgs {
+  #[command(flatten)]
+  sign: SignArgs,
+
+  /// Sign legacy transactions without `chainId` as before EIP-155, so they can be replayed on any chain.
+  #[arg(long)]
+  no_replay_protection: bool,
 }
 
-#[allow(dead_code)]
-fn prompt(msg: impl AsRef<str>) -> String {
-  use std::io::{self, Write};
+#[derive(Args, Debug)]
+struct SignMessageArgs {
+  /// Name of the key to sign with.
+  name: String,
 
-  print!("{} ", msg.as_ref().trim());
-  io::stdout().flush().unwrap();
+  /// Message to sign. If omitted, it is read from stdin as is.
+  message: Option<String>,
 
-  let mut input = String::new();
-  io::stdin().read_line(&mut input).unwrap();
+  /// Treat `message` as hex string of the raw bytes to sign.
+  #[arg(long)]
+  hex: bool,
 
-  input.trim().to_string()
-}
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
 
-fn promptpw(msg: impl AsRef<str>) -> String {
-  use std::io::{self, Write};
+  #[command(flatten)]
+  credential_input: CredentialInput,
 
-  print!("{} ", msg.as_ref().trim());
-  io::stdout().flush().unwrap();
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
 
-  read_password().unwrap()
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+
+  #[command(flatten)]
+  derive: DeriveArgs,
 }
 
-fn derive_key_v1(password: impl AsRef<str>, salt: &[u8], rounds: u32) -> [u8; 32] {
-  let password = password.as_ref().nfc().collect::<String>();
-  let bytes = password.as_bytes();
-  let mut res = [0u8; 32];
-  pbkdf2::pbkdf2::<Hmac<Sha256>>(bytes, salt, rounds, &mut res).unwrap();
-  res
+#[derive(Args, Debug)]
+struct ServeSignerArgs {
+  /// Address to listen on. Binding to anything but localhost exposes the keys to the network.
+  #[arg(long, default_value = "127.0.0.1:8550")]
+  bind: String,
+
+  /// Comma separated names of the keys to serve. Mnemonic keystores serve their first Ethereum account.
+  #[arg(long, required = true, value_delimiter = ',')]
+  keys: Vec<String>,
+
+  /// Encryption password shared by the keys. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[derive(Args, Debug)]
+struct RenderArgs {
+  /// Path of the template to render.
+  template: String,
+
+  /// Path of the file to write with permissions 0600. If omitted, the rendered template is printed to stdout.
+  #[arg(short = 'o', long)]
+  output: Option<String>,
+
+  /// Encryption password shared by all keys. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[derive(Args, Debug)]
+struct ExecArgs {
+  /// Environment variable to set to a decrypted key, given as `VAR=name`. May be repeated.
+  #[arg(short = 'e', long = "env", value_name = "VAR=NAME", required = true, value_parser = parse_env)]
+  env: Vec<(String, String)>,
+
+  /// The command to run, followed by its arguments.
+  #[arg(required = true, last = true)]
+  command: Vec<String>,
+
+  /// Encryption password shared by all keys. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[derive(Args, Debug)]
+struct PolicyCmdArgs {
+  /// Name of the keystore whose policy to show or change. Without any options, the policy is shown.
+  name: String,
+
+  /// Refuse to reveal the secret after this date, or `never`.
+  #[arg(long)]
+  not_after: Option<String>,
+
+  /// Refuse to reveal the secret after it has been read this many times, or `unlimited`.
+  #[arg(long)]
+  max_reads: Option<String>,
+
+  /// Reset the number of times the secret has been read.
+  #[arg(long)]
+  reset_reads: bool,
+
+  /// Ask for confirmation each time the secret is revealed.
+  #[arg(long)]
+  confirm: Option<bool>,
+
+  /// Refuse to reveal the secret in `--silent` mode.
+  #[arg(long)]
+  refuse_silent: Option<bool>,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[derive(Args, Debug)]
+struct RecipientsArgs {
+  #[command(subcommand)]
+  command: RecipientsCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum RecipientsCommands {
+  Add(RecipientArgs),
+  Remove(RemoveRecipientArgs),
+  List(ListRecipientsArgs),
+}
+
+#[derive(Args, Debug)]
+struct RecipientArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// X25519 public key of the recipient, e.g. `age1...` as printed by `keygen` or `age-keygen`.
+  recipient: Recipient,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[derive(Args, Debug)]
+struct RemoveRecipientArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// X25519 public key of the recipient, e.g. `age1...` as printed by `keygen` or `age-keygen`.
+  recipient: Recipient,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  password_input: PasswordInput,
+
+  #[command(flatten)]
+  slot_credentials: SlotCredentials,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+// Credentials of further key slots, which are re-wrapped under a fresh data key when a recipient is removed.
+#[derive(Args, Debug)]
+struct SlotCredentials {
+  /// Password of another key slot to re-wrap when removing a recipient. May be repeated.
+  #[arg(long)]
+  slot_password: Vec<String>,
+
+  /// Like `slot_password`, but read from the first line of the given file. May be repeated.
+  #[arg(long)]
+  slot_password_file: Vec<String>,
+
+  /// Like `slot_password`, but read from the first line of the given file descriptor. May be repeated.
+  #[arg(long)]
+  slot_password_fd: Vec<u32>,
+
+  /// Keyfile of another key slot to re-wrap when removing a recipient. May be repeated.
+  #[arg(long)]
+  slot_keyfile: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct ListRecipientsArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct SlotArgs {
+  #[command(subcommand)]
+  command: SlotCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum SlotCommands {
+  Add(AddSlotArgs),
+  Remove(RemoveSlotArgs),
+  List(ListSlotsArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddSlotArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// Encryption password of an existing slot. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Password of the new slot. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(long, env = "KAYRING_NEW_PASSWORD")]
+  new_password: Option<String>,
+
+  /// Read the password of the new slot from the first line of the given file.
+  #[arg(long)]
+  new_password_file: Option<String>,
+
+  /// Read the password of the new slot from the first line of the given file descriptor.
+  #[arg(long, conflicts_with = "new_password_file")]
+  new_password_fd: Option<u32>,
+
+  /// Add a slot for the contents of the given keyfile instead of a password. It must hold enough entropy on its own,
+  /// e.g. 32 random bytes.
+  #[arg(long, conflicts_with_all = ["new_password", "new_password_file", "new_password_fd"])]
+  new_keyfile: Option<String>,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Key derivation settings of the new password slot. `derivation_rounds` also decrypts legacy v1 keystores.
+  #[command(flatten)]
+  kdf: KdfArgs,
+}
+
+#[derive(Args, Debug)]
+struct RemoveSlotArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// Index of the slot to remove, as shown by `slot list`.
+  index: usize,
+
+  /// Encryption password of any slot. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  #[command(flatten)]
+  slot_credentials: SlotCredentials,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ListSlotsArgs {
+  /// Name of the keystore.
+  name: String,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct InitMasterArgs {
+  /// Password of the master key, which existing keystores must share. If omitted and `silent`, this will be assumed to
+  /// be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  password_input: PasswordInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Key derivation settings of the master key. `derivation_rounds` also decrypts legacy v1 keystores.
+  #[command(flatten)]
+  kdf: KdfArgs,
+}
+
+#[derive(Args, Debug)]
+struct KeygenArgs {
+  /// Path of the identity file to write with permissions 0600. If omitted, the identity is printed to stdout.
+  #[arg(short = 'o', long)]
+  output: Option<String>,
+}
+
+#[cfg(unix)]
+#[derive(Args, Debug)]
+struct AgentArgs {
+  /// Path of the socket to listen on. Defaults to `$XDG_RUNTIME_DIR/kayring-agent.sock` or `~/.kayring-agent.sock`.
+  #[arg(long, env = "KAYRING_AGENT_SOCK")]
+  socket: Option<String>,
+
+  /// Do not output logs.
+  #[arg(short = 's', long)]
+  silent: bool,
+}
+
+#[cfg(unix)]
+#[derive(Args, Debug)]
+struct UnlockArgs {
+  /// Name of the key to unlock.
+  name: String,
+
+  /// How long the agent holds the key, e.g. `30s`, `15m` or `1h30m`.
+  #[arg(long, default_value = "15m", value_parser = parse_ttl)]
+  ttl: Duration,
+
+  /// Path of the agent's socket.
+  #[arg(long, env = "KAYRING_AGENT_SOCK")]
+  socket: Option<String>,
+
+  /// Encryption password. If omitted and `silent`, this will be assumed to be an empty string.
+  #[arg(short = 'p', long, env = "KAYRING_PASSWORD")]
+  password: Option<String>,
+
+  #[command(flatten)]
+  credential_input: CredentialInput,
+
+  /// Do not output logs or prompt for input.
+  #[arg(short = 's', long)]
+  silent: bool,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+
+  /// Number of rounds to derive the encryption key. Only used for legacy v1 keystores, which do not record it.
+  #[arg(short, long, default_value = "100000", env = "KAYRING_DERIVATION_ROUNDS")]
+  derivation_rounds: u32,
+}
+
+#[cfg(unix)]
+#[derive(Args, Debug)]
+struct LockArgs {
+  /// Name of the key to lock.
+  #[arg(required_unless_present = "all", conflicts_with = "all")]
+  name: Option<String>,
+
+  /// Lock all keys held by the agent.
+  #[arg(short = 'a', long)]
+  all: bool,
+
+  /// Path of the agent's socket.
+  #[arg(long, env = "KAYRING_AGENT_SOCK")]
+  socket: Option<String>,
+
+  /// Path to the directory where the keystores are saved
+  #[arg(long, env = "KAYRING_DIR")]
+  dir: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct DeriveArgs {
+  /// For mnemonic keystores, BIP-32 derivation path of the key. Defaults to the chain's preset.
+  #[arg(long)]
+  path: Option<String>,
+
+  /// Address index of the chain preset for mnemonic keystores.
+  #[arg(long, default_value = "0", conflicts_with = "path")]
+  index: u32,
+}
+
+impl PasswordInput {
+  /// The password read from the given file or file descriptor, or else `password`, or else the output of the
+  /// password command run for the given comma separated keystore names.
+  fn resolve(&self, password: Option<String>, keystores: &str) -> Result<Option<String>> {
+    if let Some(input) = read_secret_input(self.password_file.clone(), self.password_fd)? {
+      return Ok(Some(input));
+    }
+    match (password, &self.password_command) {
+      (Some(password), _) => Ok(Some(password)),
+      (None, Some(command)) => run_password_command(command, keystores).map(Some),
+      (None, None) => Ok(None),
+    }
+  }
+}
+
+impl CredentialInput {
+  /// The credential unlocking existing keystores: the identities of the identity file or the contents of the keyfile if
+  /// given, or else the password as resolved by [`PasswordInput::resolve`], prompted for unless `silent`.
+  fn credential(&self, pw: Option<String>, keystores: &str, silent: bool) -> Result<Credential> {
+    if let Some(path) = &self.identity {
+      return Ok(Credential::Identities(Identity::parse_file(&read_file(path)?)?));
+    }
+    if let Some(path) = &self.keyfile {
+      return Ok(Credential::Keyfile(read_keyfile(path)?));
+    }
+    Ok(Credential::Password(password(self.password_input.resolve(pw, keystores)?, silent, "password")))
+  }
+}
+
+impl SlotCredentials {
+  /// The given credential followed by the credentials of the further slots. Passwords may unlock the master key.
+  fn credentials(&self, keyring: &Keyring, keystore: &Keystore, credential: Credential) -> Result<Vec<Credential>> {
+    let mut credentials = vec![credential];
+    let passwords = self.slot_password.iter().cloned()
+      .map(Ok)
+      .chain(self.slot_password_file.iter().map(|path| read_secret_input(Some(path.clone()), None).map(Option::unwrap_or_default)))
+      .chain(self.slot_password_fd.iter().map(|fd| read_secret_input(None, Some(*fd)).map(Option::unwrap_or_default)));
+    for password in passwords {
+      credentials.push(keyring.unlock_for(keystore, Credential::Password(password?))?);
+    }
+    for path in &self.slot_keyfile {
+      credentials.push(Credential::Keyfile(read_keyfile(path)?));
+    }
+    Ok(credentials)
+  }
+}
+
+impl PolicyArgs {
+  fn to_policy(&self) -> Policy {
+    Policy {
+      not_after: self.not_after,
+      max_reads: self.max_reads,
+      reads: 0,
+      confirm: self.confirm,
+      refuse_silent: self.refuse_silent,
+    }
+  }
+}
+
+impl KdfArgs {
+  fn to_kdf(&self) -> Kdf {
+    match self.kdf {
+      KdfKind::Pbkdf2 => Kdf::Pbkdf2Sha256 { rounds: self.derivation_rounds },
+      KdfKind::Argon2id => Kdf::Argon2id {
+        memory_cost: self.memory_cost,
+        time_cost: self.time_cost,
+        parallelism: self.parallelism,
+      },
+      KdfKind::Scrypt => Kdf::Scrypt {
+        log_n: self.log_n,
+        block_size: self.block_size,
+        parallelism: self.parallelism,
+      },
+    }
+  }
+}
+
+fn main() {
+  let cli = Cli::parse();
+
+  let res = match cli.command {
+    Commands::Set(args) => sub_set(args),
+    Commands::Get(args) => sub_get(args),
+    Commands::Exec(args) => sub_exec(args),
+    Commands::List(args) => sub_list(args),
+    Commands::Clone(args) => sub_clone(args),
+    Commands::Verify(args) => sub_verify(args),
+    Commands::Passwd(args) => sub_passwd(args),
+    Commands::Import(args) => sub_import(args),
+    Commands::Export(args) => sub_export(args),
+    Commands::Address(args) => sub_address(args),
+    Commands::SignTx(args) => sub_sign_tx(args),
+    Commands::SignMessage(args) => sub_sign_message(args),
+    Commands::SignTypedData(args) => sub_sign_typed_data(args),
+    Commands::SignCosmos(args) => sub_sign_cosmos(args),
+    Commands::ServeSigner(args) => sub_serve_signer(args),
+    Commands::Render(args) => sub_render(args),
+    Commands::Policy(args) => sub_policy(args),
+    Commands::Recipients(args) => sub_recipients(args),
+    Commands::Slot(args) => sub_slot(args),
+    Commands::Keygen(args) => sub_keygen(args),
+    Commands::InitMaster(args) => sub_init_master(args),
+    #[cfg(unix)]
+    Commands::Agent(args) => sub_agent(args),
+    #[cfg(unix)]
+    Commands::Unlock(args) => sub_unlock(args),
+    #[cfg(unix)]
+    Commands::Lock(args) => sub_lock(args),
+  };
+  if let Err(e) = res {
+    eprintln!("{}", e);
+    std::process::exit(e.exit_code());
+  }
+}
+
+fn sub_set(args: SetArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?;
+
+  if keyring.exists(&args.name) && !args.force {
+    return Err(Error::AlreadyExists(args.name));
+  }
+
+  let password = new_password(args.password_input.resolve(args.password, &args.name)?, args.silent, "password")?;
+
+  let value = if args.value_stdin {
+    Some(trim_newline(read_stdin()?))
+  } else if let Some(path) = args.value_file {
+    Some(trim_newline(read_file(&path)?))
+  } else {
+    args.value
+  };
+  let (encoding, value) = match args.from_file {
+    Some(path) => (Encoding::File, Some(path)),
+    None => (args.encoding, value),
+  };
+
+  let privkey = value.ok_or(())
+    .or_else(|_| {
+      if args.silent {
+        return Err(Error::InvalidValue("Value is required in silent mode".to_string()));
+      }
+      Ok(match encoding {
+        _ if args.mnemonic => promptpw("Enter mnemonic:"),
+        Encoding::File => prompt("Enter path:"),
+        _ => promptpw("Enter value:"),
+      })
+    })?;
+
+  let mut metadata = Metadata { encoding, policy: args.policy.to_policy(), ..Default::default() };
+  let value = if args.mnemonic {
+    metadata.kind = SecretKind::Bip39Seed;
+    let passphrase = read_secret_input(args.mnemonic_passphrase_file, args.mnemonic_passphrase_fd)?
+      .or(args.mnemonic_passphrase)
+      .unwrap_or_default();
+    hd::mnemonic_to_seed(&privkey, &passphrase)?.to_vec()
+  } else if encoding == Encoding::File {
+    std::fs::read(&privkey)
+      .map_err(|err| Error::io(format!("Could not read from file {}", privkey), err))?
+  } else {
+    encoding.decode(privkey.as_bytes())?
+  };
+
+  if !args.silent {
+    println!("Encrypting...");
+  }
+
+  let keystore = keyring.encrypt(&value, password, args.kdf.to_kdf(), metadata)?;
+  store(&keyring, &args.name, &keystore, args.force)?;
+
+  if args.echo {
+    println!("{}", privkey);
+  }
+
+  Ok(())
+}
+
+fn sub_get(args: GetArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+
+  let keystore = keyring.open(&args.name)?;
+  let mut cleartext = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let mut encoding = keystore.metadata().encoding;
+  if let Some(path) = args.path {
+    if keystore.metadata().kind != SecretKind::Bip39Seed {
+      return Err(Error::InvalidValue(format!("{} does not hold a mnemonic", args.name)));
+    }
+    let (path, curve) = hd::preset(&path, args.index).unwrap_or((path, hd::Curve::Secp256k1));
+    cleartext = hd::derive(&cleartext, &path, curve)?.to_vec();
+    encoding = Encoding::Hex;
+  }
+  let output = encoding.encode(&cleartext)?;
+
+  match args.to_file {
+    Some(file) => write_private(&file, &output),
+    None => {
+      use std::io::Write;
+
+      // binary files are written as is, so they can be redirected to a file
+      let mut stdout = std::io::stdout();
+      stdout.write_all(&output)
+        .and_then(|_| if encoding == Encoding::File { Ok(()) } else { stdout.write_all(b"\n") })
+        .map_err(|err| Error::io("Could not write to stdout", err))
+    },
+  }
+}
+
+fn sub_exec(args: ExecArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystores = args.env.iter()
+    .map(|(_, name)| keyring.open(name))
+    .collect::<Result<Vec<_>>>()?;
+
+  // prompt for the shared password at most once, and only if the agent does not hold all keys
+  let names = args.env.iter().map(|(_, name)| name.as_str()).collect::<Vec<_>>().join(",");
+  let mut credential_cache = CredentialCache::default();
+  let mut command = std::process::Command::new(&args.command[0]);
+  command.args(&args.command[1..]);
+  for var in SECRET_ENV_VARS {
+    command.env_remove(var);
+  }
+  for ((var, name), keystore) in args.env.iter().zip(keystores) {
+    let secret = decrypt_with(&keyring, name, &keystore, args.silent, || {
+      shared_credential(&keyring, &mut credential_cache, &keystore, &args.credential_input, &args.password, &names, args.silent)
+    })?;
+    let value = keystore.metadata().encoding.encode_string(&secret)
+      .map_err(|_| Error::InvalidValue(format!("{} cannot be passed in an environment variable", name)))?;
+    command.env(var, value);
+  }
+
+  let context = || format!("Could not run {}", args.command[0]);
+
+  // replace this process so the command receives signals sent to it & its exit status is passed on
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::CommandExt;
+    Err(Error::io(context(), command.exec()))
+  }
+
+  #[cfg(not(unix))]
+  {
+    let status = command.status().map_err(|err| Error::io(context(), err))?;
+    std::process::exit(status.code().unwrap_or(1));
+  }
+}
+
+fn sub_list(args: ListArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?;
+  let (names, has_errs) = keyring.list_with_errors()?;
+  println!("{}", names.join(", "));
+
+  if has_errs {
+    eprintln!("Some entries could not be read.");
+  }
+  Ok(())
+}
+
+fn sub_clone(args: CloneArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?;
+  keyring.clone(&args.from, &args.to, args.force)?;
+  agent_forget(&keyring, &args.to)
+}
+
+fn sub_verify(args: VerifyArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let names = if args.all { keyring.list()? } else { args.names };
+
+  let credential = args.credential_input.credential(args.password, &names.join(","), args.silent)?;
+  let mut credentials = CredentialCache { credential: Some(credential), master: None };
+
+  let mut exit_code = 0;
+  for name in names {
+    // verification neither counts as a read nor is subject to the policy
+    let unlocked = keyring.open(&name).and_then(|keystore| {
+      let credential = shared_credential(&keyring, &mut credentials, &keystore, &args.credential_input, &None, &name, args.silent)?;
+      keystore.unlock(&credential)
+    });
+    let (status, code) = match unlocked {
+      Ok(_) => ("OK".to_string(), 0),
+      Err(err) => {
+        let status = match &err {
+          Error::NotFound(_) => "not found".to_string(),
+          Error::WrongPassword => "wrong credentials".to_string(),
+          Error::Corrupt { reason, .. } => format!("corrupt ({})", reason),
+          Error::UnknownVersion(ver) => format!("unknown version {}", ver),
+          _ => return Err(err),
+        };
+        (status, err.exit_code())
+      },
+    };
+
+    if !args.silent {
+      println!("{}: {}", name, status);
+    }
+    if exit_code == 0 {
+      exit_code = code;
+    }
+  }
+
+  if exit_code != 0 {
+    std::process::exit(exit_code);
+  }
+  Ok(())
+}
+
+fn sub_passwd(args: PasswdArgs) -> Result<()> {
+  let keyring = keyring(args.dir.clone())?.with_legacy_rounds(args.old_derivation_rounds);
+  if keyring.has_master_key() {
+    return passwd_master(&keyring, args);
+  }
+  let names = if args.all { keyring.list()? } else { args.names };
+
+  let keystores = names.iter()
+    .map(|name| keyring.open(name))
+    .collect::<Result<Vec<_>>>()?;
+
+  let password = password(args.password_input.resolve(args.password, &names.join(","))?, args.silent, "current password");
+
+  // decrypt everything first so we do not end up with a keyring using mixed passwords
+  for (name, keystore) in names.iter().zip(keystores.iter()) {
+    keystore.decrypt(&password).inspect_err(|_| eprintln!("Failed to decrypt {}", name))?;
+  }
+
+  let new_password = match read_secret_input(args.new_password_file, args.new_password_fd)? {
+    Some(input) => input,
+    None => new_password(args.new_password, args.silent, "new password")?,
+  };
+
+  let kdf = args.kdf.to_kdf();
+  for (name, keystore) in names.iter().zip(keystores) {
+    if !args.silent {
+      println!("Re-encrypting {}...", name);
+    }
+    // v3 keystores only re-wrap their data key, so their recipients retain access
+    store(&keyring, name, &keystore.rekey(&password, &new_password, kdf.clone())?, true)?;
+  }
+
+  Ok(())
+}
+
+/// Wrap the master key under the new password. The keystores themselves remain unchanged.
+fn passwd_master(keyring: &Keyring, args: PasswdArgs) -> Result<()> {
+  if !args.all {
+    return Err(Error::InvalidValue(
+      "The keystores share the master key of the keyring. Change its password with `passwd --all`".to_string(),
+    ));
+  }
+
+  let names = keyring.list()?.join(",");
+  let password = password(args.password_input.resolve(args.password, &names)?, args.silent, "current password");
+  let new_password = match read_secret_input(args.new_password_file, args.new_password_fd)? {
+    Some(input) => input,
+    None => new_password(args.new_password, args.silent, "new password")?,
+  };
+
+  if !args.silent {
+    println!("Re-wrapping the master key...");
+  }
+  keyring.rekey_master(password, new_password, args.kdf.to_kdf())
+}
+
+fn sub_import(args: ImportArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?;
+
+  if keyring.exists(&args.name) && !args.force {
+    return Err(Error::AlreadyExists(args.name));
+  }
+
+  let contents = std::fs::read(&args.file)
+    .map_err(|err| Error::io(format!("Could not read from file {}", args.file), err))?;
+  let file_password = read_secret_input(args.file_password_file, args.file_password_fd)?.or(args.file_password);
+
+  let mut metadata = Metadata { policy: args.policy.to_policy(), ..Default::default() };
+  let value = match args.format {
+    FileFormat::EthKeystore => {
+      let contents = std::str::from_utf8(&contents)
+        .map_err(|_| Error::InvalidValue(format!("{} is not a JSON file", args.file)))?;
+      eth_keystore::decrypt(contents, password(file_password, args.silent, "file password"))?
+    },
+    FileFormat::Age => {
+      let credential = match (age_file::is_passphrase_encrypted(&contents)?, args.file_identity) {
+        (true, _) => Credential::Password(password(file_password, args.silent, "file password")),
+        (false, Some(path)) => Credential::Identities(Identity::parse_file(&read_file(&path)?)?),
+        (false, None) => {
+          return Err(Error::InvalidValue(format!("{} is encrypted to recipients, pass --file-identity", args.file)));
+        },
+      };
+      let value = age_file::decrypt(&contents, &credential)?;
+
+      metadata.encoding = args.encoding;
+      if args.encoding == Encoding::File {
+        value
+      } else {
+        let value = String::from_utf8(value)
+          .map_err(|_| Error::InvalidValue(format!("Value must be valid UTF-8 for encoding {}", args.encoding)))?;
+        args.encoding.decode(trim_newline(value).as_bytes())?
+      }
+    },
+  };
+
+  let password = new_password(args.password_input.resolve(args.password, &args.name)?, args.silent, "password")?;
+
+  if !args.silent {
+    println!("Encrypting...");
+  }
+
+  let keystore = keyring.encrypt(&value, password, args.kdf.to_kdf(), metadata)?;
+  store(&keyring, &args.name, &keystore, args.force)
+}
+
+fn sub_export(args: ExportArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let value = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let file_password = read_secret_input(args.file_password_file, args.file_password_fd)?.or(args.file_password);
+
+  let contents = match args.format {
+    FileFormat::EthKeystore => {
+      let file_password = new_password(file_password, args.silent, "file password")?;
+      let log_n = if args.light { eth_keystore::LIGHT_LOG_N } else { eth_keystore::STANDARD_LOG_N };
+      format!("{}\n", eth_keystore::encrypt(&value, file_password, log_n)?).into_bytes()
+    },
+    // age files hold the value as `get` would print it, so `age --decrypt` yields the same
+    FileFormat::Age => {
+      let value = keystore.metadata().encoding.encode(&value)?;
+      if args.recipients.is_empty() {
+        let file_password = new_password(file_password, args.silent, "file password")?;
+        age_file::encrypt_with_passphrase(&value, file_password, args.armor)?
+      } else {
+        age_file::encrypt_to_recipients(&value, &args.recipients, args.armor)?
+      }
+    },
+  };
+
+  match args.output {
+    Some(output) => write_private(&output, &contents),
+    None => {
+      use std::io::Write;
+
+      std::io::stdout().write_all(&contents)
+        .map_err(|err| Error::io("Could not write to stdout", err))
+    },
+  }
+}
+
+fn sub_address(args: AddressArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let privkey = chain_key(&args.name, &keystore, secret, &args.chain, args.derive)?;
+
+  let account = args.chain.account(&privkey)?;
+  if args.short {
+    println!("{}", account.address);
+  } else {
+    println!("address: {}", account.address);
+    println!("public key: {}", account.public_key);
+  }
+  Ok(())
+}
+
+fn sub_sign_tx(SignTxArgs { sign: args, no_replay_protection }: SignTxArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let input = read_stdin()?;
+  let tx = Transaction::parse(&input)?;
+
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let privkey = chain_key(&args.name, &keystore, secret, &Chain::Eth, args.derive)?;
+
+  let raw = if no_replay_protection { tx.sign_without_replay_protection(&privkey)? } else { tx.sign(&privkey)? };
+  println!("0x{}", hex::encode(raw));
+  Ok(())
+}
+
+fn sub_sign_message(args: SignMessageArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let message = match args.message {
+    Some(message) => message,
+    None => read_stdin()?,
+  };
+  let message = if args.hex {
+    hex::decode(message.trim().trim_start_matches("0x"))
+      .map_err(|_| Error::InvalidValue("Message must be a valid hex string".to_string()))?
+  } else {
+    message.into_bytes()
+  };
+
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let privkey = chain_key(&args.name, &keystore, secret, &Chain::Eth, args.derive)?;
+
+  println!("0x{}", hex::encode(eth::sign_message(&privkey, &message)?.to_bytes()));
+  Ok(())
+}
+
+fn sub_sign_typed_data(args: SignArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let hash = TypedData::from_json(&read_stdin()?)?.signing_hash()?;
+
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let privkey = chain_key(&args.name, &keystore, secret, &Chain::Eth, args.derive)?;
+
+  println!("0x{}", hex::encode(eth::sign_hash(&privkey, &hash)?.to_bytes()));
+  Ok(())
+}
+
+fn sub_sign_cosmos(args: SignArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let doc = SignDoc::parse(&read_stdin()?)?;
+
+  let chain = Chain::Cosmos("cosmos".to_string());
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  let privkey = chain_key(&args.name, &keystore, secret, &chain, args.derive)?;
+
+  println!("{}", doc.sign(&privkey)?);
+  Ok(())
+}
+
+fn sub_serve_signer(args: ServeSignerArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystores = args.keys.iter()
+    .map(|name| keyring.open(name))
+    .collect::<Result<Vec<_>>>()?;
+
+  // prompt for the shared password at most once, and only if the agent does not hold all keys
+  let mut credential_cache = CredentialCache::default();
+  let mut signer = Signer::new().with_silent(args.silent);
+  for (name, keystore) in args.keys.iter().zip(keystores) {
+    let secret = decrypt_with(&keyring, name, &keystore, args.silent, || {
+      shared_credential(&keyring, &mut credential_cache, &keystore, &args.credential_input, &args.password, &args.keys.join(","), args.silent)
+    })?;
+    let derive = DeriveArgs { path: None, index: 0 };
+    let address = signer.add_key(chain_key(name, &keystore, secret, &Chain::Eth, derive)?)?;
+    if !args.silent {
+      println!("{}: {}", name, eth::to_checksum_address(&address));
+    }
+  }
+
+  if !args.silent {
+    println!("Listening on {}", args.bind);
+  }
+  signer.serve(&args.bind)
+}
+
+fn sub_render(args: RenderArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+
+  let template = Template::parse(&read_file(&args.template)?)?;
+
+  // open every keystore before decrypting any, so a missing key fails early
+  let names = template.names();
+  let keystores = names.iter()
+    .map(|name| keyring.open(name))
+    .collect::<Result<Vec<_>>>()?;
+
+  let mut credential_cache = CredentialCache::default();
+  let mut secrets = HashMap::new();
+  for (name, keystore) in names.iter().zip(keystores) {
+    let secret = decrypt_with(&keyring, name, &keystore, args.silent, || {
+      shared_credential(&keyring, &mut credential_cache, &keystore, &args.credential_input, &args.password, &names.join(","), args.silent)
+    })?;
+    secrets.insert(*name, (keystore, secret));
+  }
+
+  let output = template.render(|name, filter| {
+    let (keystore, secret) = &secrets[name];
+    match filter {
+      Filter::Value => keystore.metadata().encoding.encode_string(secret)
+        .map_err(|_| Error::InvalidValue(format!("{} cannot be rendered into a template", name))),
+      Filter::Hex => Ok(format!("0x{}", hex::encode(secret))),
+      Filter::NoHex => Ok(hex::encode(secret)),
+      Filter::Address(chain) => {
+        let derive = DeriveArgs { path: None, index: 0 };
+        let privkey = chain_key(name, keystore, secret.clone(), chain, derive)?;
+        Ok(chain.account(&privkey)?.address)
+      },
+    }
+  })?;
+
+  match args.output {
+    Some(path) => write_private(&path, output.as_bytes()),
+    None => {
+      print!("{}", output);
+      Ok(())
+    },
+  }
+}
+
+fn sub_policy(args: PolicyCmdArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+  let mut metadata = keystore.metadata();
+
+  let unchanged = args.not_after.is_none() && args.max_reads.is_none() && !args.reset_reads
+    && args.confirm.is_none() && args.refuse_silent.is_none();
+  if unchanged {
+    println!("{}", metadata.policy);
+    return Ok(());
+  }
+
+  let policy = &mut metadata.policy;
+  if let Some(not_after) = args.not_after {
+    policy.not_after = match not_after.as_str() {
+      "never" => None,
+      date => Some(Policy::parse_date(date)?),
+    };
+  }
+  if let Some(max_reads) = args.max_reads {
+    policy.max_reads = match max_reads.as_str() {
+      "unlimited" => None,
+      n => Some(n.parse().map_err(|_| Error::InvalidValue(format!("Invalid read limit {}", n)))?),
+    };
+  }
+  if args.reset_reads || policy.max_reads.is_none() {
+    policy.reads = 0;
+  }
+  if let Some(confirm) = args.confirm {
+    policy.confirm = confirm;
+  }
+  if let Some(refuse_silent) = args.refuse_silent {
+    policy.refuse_silent = refuse_silent;
+  }
+
+  // the policy may always be tightened, but relaxing it is subject to the current policy, so it cannot lift
+  // restrictions once they are reached
+  let current = keystore.metadata().policy;
+  if current.is_relaxed_by(&metadata.policy) {
+    current.check(&args.name)?;
+    confirm_access(&args.name, &current, args.silent, "Relax the policy of")?;
+  }
+
+  let credential = args.credential_input.credential(args.password, &args.name, args.silent)?;
+  let credential = keyring.unlock_for(&keystore, credential)?;
+  let value = keystore.unlock(&credential)?;
+  store(&keyring, &args.name, &keystore.reencrypt(&value, &credential, metadata.clone())?, true)?;
+
+  if !args.silent {
+    println!("{}", metadata.policy);
+  }
+  Ok(())
+}
+
+fn sub_recipients(args: RecipientsArgs) -> Result<()> {
+  match args.command {
+    RecipientsCommands::Add(args) => {
+      let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+      let keystore = keyring.open(&args.name)?;
+
+      // adding a recipient does not reveal the secret, so it is not subject to the policy
+      let credential = args.credential_input.credential(args.password, &args.name, args.silent)?;
+      let credential = keyring.unlock_for(&keystore, credential)?;
+      store(&keyring, &args.name, &keystore.add_recipient(&credential, &args.recipient)?, true)?;
+      if !args.silent {
+        println!("Added {} to {}", args.recipient, args.name);
+      }
+      Ok(())
+    },
+    RecipientsCommands::Remove(args) => {
+      let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+      let keystore = keyring.open(&args.name)?;
+
+      // the data key is replaced, which requires the password, so the removed recipient cannot remove others
+      let password = password(args.password_input.resolve(args.password, &args.name)?, args.silent, "password");
+      let credential = keyring.unlock_for(&keystore, Credential::Password(password))?;
+      let credentials = args.slot_credentials.credentials(&keyring, &keystore, credential)?;
+      store(&keyring, &args.name, &keystore.remove_recipient(&credentials, &args.recipient)?, true)?;
+      if !args.silent {
+        println!("Removed {} from {}", args.recipient, args.name);
+      }
+      Ok(())
+    },
+    RecipientsCommands::List(args) => {
+      let keyring = keyring(args.dir)?;
+      for recipient in keyring.open(&args.name)?.recipients() {
+        println!("{}", recipient);
+      }
+      Ok(())
+    },
+  }
+}
+
+fn sub_slot(args: SlotArgs) -> Result<()> {
+  match args.command {
+    SlotCommands::Add(args) => {
+      let keyring = keyring(args.dir)?.with_legacy_rounds(args.kdf.derivation_rounds);
+      let keystore = keyring.open(&args.name)?;
+
+      let credential = args.credential_input.credential(args.password, &args.name, args.silent)?;
+      let credential = keyring.unlock_for(&keystore, credential)?;
+      let new_credential = match args.new_keyfile {
+        Some(path) => Credential::Keyfile(read_keyfile(&path)?),
+        None => Credential::Password(match read_secret_input(args.new_password_file, args.new_password_fd)? {
+          Some(input) => input,
+          None => new_password(args.new_password, args.silent, "new password")?,
+        }),
+      };
+
+      let keystore = keystore.add_slot(&credential, &new_credential, args.kdf.to_kdf())?;
+      store(&keyring, &args.name, &keystore, true)?;
+      if !args.silent {
+        println!("Added slot {} to {}", keystore.slots().len() - 1, args.name);
+      }
+      Ok(())
+    },
+    SlotCommands::Remove(args) => {
+      let keyring = keyring(args.dir)?;
+      let keystore = keyring.open(&args.name)?;
+
+      let credential = args.credential_input.credential(args.password, &args.name, args.silent)?;
+      let credential = keyring.unlock_for(&keystore, credential)?;
+      let credentials = args.slot_credentials.credentials(&keyring, &keystore, credential)?;
+      store(&keyring, &args.name, &keystore.remove_slot(&credentials, args.index)?, true)?;
+      if !args.silent {
+        println!("Removed slot {} from {}", args.index, args.name);
+      }
+      Ok(())
+    },
+    SlotCommands::List(args) => {
+      let keyring = keyring(args.dir)?;
+      let keystore = keyring.open(&args.name)?;
+
+      // older keystores are encrypted under their password directly, akin to a single slot
+      if let Some(header) = keystore.header() {
+        println!("0: password ({})", kdf_name(&header.kdf));
+      } else if keystore.version() == 1 {
+        println!("0: password (pbkdf2)");
+      }
+      for (index, slot) in keystore.slots().iter().enumerate() {
+        match slot {
+          Slot::Password { kdf, .. } => println!("{}: password ({})", index, kdf_name(kdf)),
+          Slot::Keyfile { .. } => println!("{}: keyfile", index),
+          Slot::X25519 { recipient, .. } => println!("{}: recipient {}", index, Recipient::from_bytes(*recipient)),
+          Slot::Master { .. } => println!("{}: master key", index),
+        }
+      }
+      Ok(())
+    },
+  }
+}
+
+fn kdf_name(kdf: &Kdf) -> &'static str {
+  match kdf {
+    Kdf::Pbkdf2Sha256 { .. } => "pbkdf2",
+    Kdf::Argon2id { .. } => "argon2id",
+    Kdf::Scrypt { .. } => "scrypt",
+  }
+}
+
+fn sub_init_master(args: InitMasterArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.kdf.derivation_rounds);
+  let names = keyring.list().unwrap_or_default().join(",");
+
+  let password = if keyring.has_master_key() {
+    password(args.password_input.resolve(args.password, &names)?, args.silent, "password")
+  } else {
+    new_password(args.password_input.resolve(args.password, &names)?, args.silent, "password")?
+  };
+
+  if !args.silent {
+    println!("Wrapping keystores under the master key...");
+  }
+  keyring.init_master(password, args.kdf.to_kdf())
+}
+
+fn sub_keygen(args: KeygenArgs) -> Result<()> {
+  let identity = Identity::generate();
+  let contents = format!("# public key: {}\n{}\n", identity.to_public(), identity);
+  match args.output {
+    Some(path) => {
+      write_private(&path, contents.as_bytes())?;
+      eprintln!("Public key: {}", identity.to_public());
+      Ok(())
+    },
+    None => {
+      print!("{}", contents);
+      Ok(())
+    },
+  }
+}
+
+#[cfg(unix)]
+fn sub_agent(args: AgentArgs) -> Result<()> {
+  let socket = agent_client(args.socket)?.socket().to_path_buf();
+  let agent = Agent::bind(socket)?;
+  if !args.silent {
+    println!("Listening on {}", agent.socket().to_string_lossy());
+  }
+  agent.run()
+}
+
+#[cfg(unix)]
+fn sub_unlock(args: UnlockArgs) -> Result<()> {
+  let keyring = keyring(args.dir)?.with_legacy_rounds(args.derivation_rounds);
+  let keystore = keyring.open(&args.name)?;
+
+  let client = agent_client(args.socket)?;
+  if !client.is_running() {
+    return Err(Error::Other(format!(
+      "No agent is listening on {}. Start one with `kayring agent`",
+      client.socket().to_string_lossy(),
+    )));
+  }
+
+  // the agent could not count reads
+  if keystore.metadata().policy.max_reads.is_some() {
+    return Err(Error::Denied(format!("{} has a read limit and cannot be held by the agent", args.name)));
+  }
+  let secret = decrypt(&keyring, &args.name, &keystore, &args.credential_input, args.password, args.silent)?;
+  client.unlock(&keyring.path(&args.name), &secret, args.ttl)
+}
+
+#[cfg(unix)]
+fn sub_lock(args: LockArgs) -> Result<()> {
+  let client = agent_client(args.socket)?;
+  match args.name {
+    Some(name) => {
+      let keyring = keyring(args.dir)?;
+      if !keyring.exists(&name) {
+        return Err(Error::NotFound(name));
+      }
+      client.lock(&keyring.path(&name))?;
+      Ok(())
+    },
+    None => client.lock_all(),
+  }
+}
+
+/// Decrypt the secret of the given keystore, obtaining the credential unless the agent holds it.
+fn decrypt(
+  keyring: &Keyring,
+  name: &str,
+  keystore: &Keystore,
+  input: &CredentialInput,
+  pw: Option<String>,
+  silent: bool,
+) -> Result<Vec<u8>> {
+  decrypt_with(keyring, name, keystore, silent, || input.credential(pw, name, silent))
+}
+
+/// Like [`decrypt`], but takes the credential from the given function. Enforces the keystore's policy.
+/// Secrets with a read limit are always decrypted from the keystore, so the read is counted.
+fn decrypt_with(
+  keyring: &Keyring,
+  name: &str,
+  keystore: &Keystore,
+  silent: bool,
+  credential: impl FnOnce() -> Result<Credential>,
+) -> Result<Vec<u8>> {
+  let policy = keystore.metadata().policy;
+  policy.check(name)?;
+  confirm_access(name, &policy, silent, "Reveal")?;
+
+  if policy.max_reads.is_none() {
+    if let Some(secret) = agent_secret(keyring, name)? {
+      return Ok(secret);
+    }
+  }
+  keyring.get_with(name, &credential()?)
+}
+
+/// The credential shared by several keystores, obtained at most once. Passwords are resolved into the
+/// master key of the keyring for keystores wrapped under it, so it is unlocked only once as well.
+#[derive(Default)]
+struct CredentialCache {
+  credential: Option<Credential>,
+  master: Option<Credential>,
+}
+
+fn shared_credential(
+  keyring: &Keyring,
+  cache: &mut CredentialCache,
+  keystore: &Keystore,
+  input: &CredentialInput,
+  pw: &Option<String>,
+  keystores: &str,
+  silent: bool,
+) -> Result<Credential> {
+  let credential = match &cache.credential {
+    Some(credential) => credential.clone(),
+    None => cache.credential.insert(input.credential(pw.clone(), keystores, silent)?).clone(),
+  };
+  if !keystore.uses_master_key() {
+    return Ok(credential);
+  }
+  match &cache.master {
+    Some(credential) => Ok(credential.clone()),
+    // passwords not unlocking the master key fall back to the keystore's own password slots
+    None => Ok(cache.master.insert(keyring.unlock_for(keystore, credential)?).clone()),
+  }
+}
+
+/// Enforce the interactive restrictions of the given policy before the given action, e.g. `Reveal`.
+fn confirm_access(name: &str, policy: &Policy, silent: bool, action: &str) -> Result<()> {
+  if silent && policy.refuse_silent {
+    return Err(Error::Denied(format!("{} may not be read in silent mode", name)));
+  }
+  if policy.confirm {
+    if silent {
+      return Err(Error::Denied(format!("{} requires confirmation, which is not possible in silent mode", name)));
+    }
+    if !confirm(format!("{} {}? [y/N]", action, name)) {
+      return Err(Error::Denied(format!("{} was not confirmed", name)));
+    }
+  }
+  Ok(())
+}
+
+/// The private key of the given chain. For mnemonic keystores, the key is derived from the secret at
+/// the given path, or the chain's preset.
+fn chain_key(name: &str, keystore: &Keystore, secret: Vec<u8>, chain: &Chain, derive: DeriveArgs) -> Result<Vec<u8>> {
+  if keystore.metadata().kind == SecretKind::Bip39Seed {
+    let path = match derive.path {
+      Some(path) => path,
+      None => hd::preset(chain.preset(), derive.index).unwrap().0,
+    };
+    Ok(hd::derive(&secret, &path, chain.curve())?.to_vec())
+  } else if derive.path.is_some() {
+    Err(Error::InvalidValue(format!("{} does not hold a mnemonic", name)))
+  } else {
+    Ok(secret)
+  }
+}
+
+/// Store the keystore in the keyring, & make the agent forget the secret it may hold for the previous keystore
+/// of that name.
+fn store(keyring: &Keyring, name: &str, keystore: &Keystore, overwrite: bool) -> Result<()> {
+  keyring.set(name, keystore, overwrite)?;
+  agent_forget(keyring, name)
+}
+
+#[cfg(unix)]
+fn agent_forget(keyring: &Keyring, name: &str) -> Result<()> {
+  match agent_client(None) {
+    Ok(client) => client.forget(&keyring.path(name)),
+    Err(_) => Ok(()),
+  }
+}
+
+#[cfg(not(unix))]
+fn agent_forget(_keyring: &Keyring, _name: &str) -> Result<()> {
+  Ok(())
+}
+
+#[cfg(unix)]
+fn agent_secret(keyring: &Keyring, name: &str) -> Result<Option<Vec<u8>>> {
+  // without a socket path, there is no agent to ask
+  match agent_client(None) {
+    Ok(client) => client.get(&keyring.path(name)),
+    Err(_) => Ok(None),
+  }
+}
+
+#[cfg(not(unix))]
+fn agent_secret(_keyring: &Keyring, _name: &str) -> Result<Option<Vec<u8>>> {
+  Ok(None)
+}
+
+/// Client of the agent at the given socket, `KAYRING_AGENT_SOCK` or the default socket.
+#[cfg(unix)]
+fn agent_client(socket: Option<String>) -> Result<AgentClient> {
+  match socket.or_else(|| std::env::var("KAYRING_AGENT_SOCK").ok()) {
+    Some(socket) => Ok(AgentClient::new(socket)),
+    None => Ok(AgentClient::new(AgentClient::default_socket()?)),
+  }
+}
+
+/// Longest time a key may be held by the agent.
+#[cfg(unix)]
+const MAX_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+#[cfg(unix)]
+/// Parse a duration such as `90`, `15m` or `1h30m`. Bare numbers are seconds. At most 30 days.
+fn parse_ttl(value: &str) -> std::result::Result<Duration, String> {
+  let invalid = || format!("invalid duration {}, expected e.g. 30s, 15m or 1h30m", value);
+  let secs = match value.parse() {
+    Ok(secs) => secs,
+    Err(_) => parse_duration_units(value).ok_or_else(invalid)?,
+  };
+  if secs > MAX_TTL_SECS {
+    return Err(format!("duration {} exceeds the maximum of 30d", value));
+  }
+  Ok(Duration::from_secs(secs))
+}
+
+#[cfg(unix)]
+/// Parse a duration like `1h30m` into seconds.
+fn parse_duration_units(value: &str) -> Option<u64> {
+  let mut secs = 0u64;
+  let mut digits = String::new();
+  for c in value.chars() {
+    if c.is_ascii_digit() {
+      digits.push(c);
+      continue;
+    }
+    let unit = match c {
+      's' => 1,
+      'm' => 60,
+      'h' => 60 * 60,
+      'd' => 24 * 60 * 60,
+      _ => return None,
+    };
+    let amount: u64 = digits.parse().ok()?;
+    secs = amount.checked_mul(unit).and_then(|n| secs.checked_add(n))?;
+    digits.clear();
+  }
+  if !digits.is_empty() {
+    return None;
+  }
+  Some(secs)
+}
+
+/// Parse a `VAR=name` pair of `exec --env`.
+fn parse_env(value: &str) -> std::result::Result<(String, String), String> {
+  match value.split_once('=') {
+    Some((var, name)) if !var.is_empty() && !name.is_empty() => Ok((var.to_string(), name.to_string())),
+    _ => Err(format!("expected VAR=name, found {}", value)),
+  }
+}
+
+fn parse_date(value: &str) -> std::result::Result<u64, String> {
+  Policy::parse_date(value).map_err(|err| err.to_string())
+}
+
+/// Run the password command through the shell & read the password from the first line of its output. The
+/// names of the keystores are passed in `KAYRING_KEYSTORE`.
+fn run_password_command(command: &str, keystores: &str) -> Result<String> {
+  use std::process::{Command, Stdio};
+
+  let mut shell = if cfg!(windows) {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C");
+    shell
+  } else {
+    let mut shell = Command::new("sh");
+    shell.arg("-c");
+    shell
+  };
+  // stdin may hold input for kayring itself, e.g. a transaction to sign
+  let output = shell.arg(command)
+    .env("KAYRING_KEYSTORE", keystores)
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .output()
+    .map_err(|err| Error::io(format!("Could not run password command {}", command), err))?;
+  if !output.status.success() {
+    return Err(Error::Other(format!("Password command failed with {}", output.status)));
+  }
+
+  let stdout = String::from_utf8(output.stdout)
+    .map_err(|_| Error::InvalidValue("Password command printed invalid UTF-8".to_string()))?;
+  Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+/// Read the first line of the given file or file descriptor, if any.
+fn read_secret_input(file: Option<String>, fd: Option<u32>) -> Result<Option<String>> {
+  let path = match (file, fd) {
+    (Some(file), _) => file,
+    // avoids unsafely taking ownership of the raw descriptor
+    (None, Some(fd)) if cfg!(unix) => format!("/dev/fd/{}", fd),
+    (None, Some(_)) => return Err(Error::InvalidValue("File descriptors are only supported on Unix".to_string())),
+    (None, None) => return Ok(None),
+  };
+  let contents = read_file(&path)?;
+  Ok(Some(contents.lines().next().unwrap_or_default().to_string()))
+}
+
+fn read_keyfile(path: &str) -> Result<Vec<u8>> {
+  let keyfile = std::fs::read(path)
+    .map_err(|err| Error::io(format!("Could not read from file {}", path), err))?;
+  if keyfile.is_empty() {
+    return Err(Error::InvalidValue(format!("Keyfile {} is empty", path)));
+  }
+  Ok(keyfile)
+}
+
+fn read_file(path: &str) -> Result<String> {
+  std::fs::read_to_string(path)
+    .map_err(|err| Error::io(format!("Could not read from file {}", path), err))
+}
+
+/// Strip a single trailing newline as appended by most editors & `echo`.
+fn trim_newline(mut value: String) -> String {
+  if value.ends_with('\n') {
+    value.pop();
+    if value.ends_with('\r') {
+      value.pop();
+    }
+  }
+  value
+}
+
+fn read_stdin() -> Result<String> {
+  use std::io::{self, Read};
+
+  let mut input = String::new();
+  io::stdin().read_to_string(&mut input)
+    .map_err(|err| Error::io("Could not read from stdin", err))?;
+  Ok(input)
+}
+
+fn keyring(dir: Option<String>) -> Result<Keyring> {
+  match dir {
+    Some(dir) => Ok(Keyring::new(dir)),
+    None => Keyring::open_default(),
+  }
+}
+
+/// The given password, or prompt for it unless `silent`. If omitted and `silent`, it is assumed to be
+/// an empty string.
+fn password(password: Option<String>, silent: bool, what: &str) -> String {
+  password.unwrap_or_else(|| {
+    if silent {
+      "".to_string()
+    } else {
+      promptpw(format!("Enter {}:", what))
+    }
+  })
+}
+
+/// Like [`password`], but prompts twice to confirm a password which is about to be set.
+fn new_password(password: Option<String>, silent: bool, what: &str) -> Result<String> {
+  password.ok_or(())
+    .or_else(|_| {
+      if silent {
+        Ok("".to_string())
+      } else {
+        let pw = promptpw(format!("Enter {}:", what));
+        let pw2 = promptpw(format!("Confirm {}:", what));
+        if pw != pw2 {
+          Err(Error::InvalidValue("Passwords do not match".to_string()))
+        } else {
+          Ok(pw)
+        }
+      }
+    })
+}
+
+fn prompt(msg: impl AsRef<str>) -> String {
+  use std::io::{self, Write};
+
+  print!("{} ", msg.as_ref().trim());
+  io::stdout().flush().unwrap();
+
+  let mut input = String::new();
+  io::stdin().read_line(&mut input).unwrap();
+
+  input.trim().to_string()
+}
+
+/// Ask a yes/no question on the terminal, which still works when stdin is piped. Defaults to no.
+fn confirm(msg: impl AsRef<str>) -> bool {
+  use std::io::{self, BufRead, BufReader, Write};
+
+  eprint!("{} ", msg.as_ref().trim());
+  io::stderr().flush().unwrap();
+
+  let mut input = String::new();
+  let read = match std::fs::File::open("/dev/tty") {
+    Ok(tty) => BufReader::new(tty).read_line(&mut input),
+    Err(_) => io::stdin().read_line(&mut input),
+  };
+  read.is_ok() && matches!(input.trim(), "y" | "Y" | "yes")
+}
+
+fn promptpw(msg: impl AsRef<str>) -> String {
+  use std::io::{self, Write};
+
+  print!("{} ", msg.as_ref().trim());
+  io::stdout().flush().unwrap();
+
+  read_password().unwrap()
 }
@@ -0,0 +1,76 @@
+//! Master key of a keyring. A random master key is wrapped under the keyring password in the master
+//! key file, & the data keys of the keystores are wrapped under the master key in turn. Unlocking many
+//! keystores thus derives a key from the password only once, & changing the password only rewrites
+//! the master key file.
+use std::fmt;
+
+use aes_gcm::aead::{Aead, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
+
+use crate::error::{Error, Result};
+use crate::format::{self, Slot};
+use crate::kdf::Kdf;
+use crate::keystore::{password_slot, random_key, unwrap_password_slot};
+
+/// Unlocked master key of a keyring.
+#[derive(Clone)]
+pub struct MasterKey([u8; 32]);
+
+/// Contents of the master key file of a keyring.
+#[derive(Clone, Debug)]
+pub(crate) struct MasterFile {
+  slots: Vec<Slot>,
+}
+
+impl MasterFile {
+  /// Generate a new master key wrapped under the password.
+  pub fn create(password: impl AsRef<str>, kdf: Kdf) -> Result<(Self, MasterKey)> {
+    let key = random_key();
+    let slots = vec![password_slot(&key, password, kdf)?];
+    Ok((Self { slots }, MasterKey(key)))
+  }
+
+  pub fn unlock(&self, password: impl AsRef<str>) -> Result<MasterKey> {
+    for slot in &self.slots {
+      if let Some(key) = unwrap_password_slot(slot, password.as_ref())? {
+        return Ok(MasterKey(key));
+      }
+    }
+    Err(Error::WrongPassword)
+  }
+
+  /// Wrap the master key under a new password, so all keystores remain unchanged.
+  pub fn rekey(&self, password: impl AsRef<str>, new_password: impl AsRef<str>, kdf: Kdf) -> Result<Self> {
+    let master = self.unlock(password)?;
+    Ok(Self { slots: vec![password_slot(&master.0, new_password, kdf)?] })
+  }
+
+  pub fn from_bytes(contents: &[u8]) -> Result<Self> {
+    Ok(Self { slots: format::parse_master(contents)? })
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    format::write_master(&self.slots)
+  }
+}
+
+impl MasterKey {
+  /// Wrap the data key of a keystore under this master key.
+  pub(crate) fn wrap_key(&self, key: &[u8; 32]) -> Result<Slot> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let wrapped_key = Aes256Gcm::new(&self.0.into()).encrypt(&nonce, key.as_slice())
+      .map_err(|err| Error::Other(format!("Failed to wrap key: {}", err)))?;
+    Ok(Slot::Master { nonce: nonce.into(), wrapped_key })
+  }
+
+  /// Unwrap a data key wrapped under this master key. Returns `None` if it was wrapped under another.
+  pub(crate) fn unwrap_key(&self, nonce: &[u8; 12], wrapped_key: &[u8]) -> Option<[u8; 32]> {
+    Aes256Gcm::new(&self.0.into()).decrypt(nonce.into(), wrapped_key).ok()?.try_into().ok()
+  }
+}
+
+impl fmt::Debug for MasterKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("MasterKey(..)")
+  }
+}
@@ -0,0 +1,229 @@
+//! X25519 identities & recipients, encoded like those of [age](https://age-encryption.org) as
+//! `AGE-SECRET-KEY-1...` & `age1...`, so existing age keys can be used with kayring.
+//!
+//! The data key of a keystore is wrapped for a recipient with an ephemeral key pair: the wrapping key
+//! is derived from their shared secret with HKDF-SHA256, salted with both public keys.
+use std::fmt;
+use std::str::FromStr;
+
+use aes_gcm::aead::{Aead, OsRng};
+use aes_gcm::{Aes256Gcm, KeyInit};
+use bech32::{Bech32, Hrp};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::error::{Error, Result};
+
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+const WRAP_INFO: &[u8] = b"kayring/v3/x25519";
+
+/// Secret half of an X25519 key pair, able to unwrap data keys wrapped for its [`Recipient`].
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+/// Public half of an X25519 key pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Recipient([u8; 32]);
+
+impl Identity {
+  pub fn generate() -> Self {
+    Self(StaticSecret::random_from_rng(OsRng))
+  }
+
+  pub fn to_public(&self) -> Recipient {
+    Recipient(PublicKey::from(&self.0).to_bytes())
+  }
+
+  /// Parse the identities of an age identity file: one per line, ignoring empty lines & `#` comments.
+  pub fn parse_file(contents: &str) -> Result<Vec<Self>> {
+    let identities = contents.lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(str::parse)
+      .collect::<Result<Vec<_>>>()?;
+    if identities.is_empty() {
+      return Err(Error::InvalidValue("Identity file contains no identities".to_string()));
+    }
+    Ok(identities)
+  }
+
+  /// Unwrap a data key wrapped for this identity's recipient. Returns `None` if it was wrapped for
+  /// someone else.
+  pub(crate) fn unwrap_key(&self, ephemeral: &[u8; 32], wrapped_key: &[u8]) -> Option<[u8; 32]> {
+    let recipient = self.to_public();
+    let shared = self.0.diffie_hellman(&PublicKey::from(*ephemeral));
+    if !shared.was_contributory() {
+      return None;
+    }
+    let key = wrapping_key(shared.as_bytes(), ephemeral, &recipient.0);
+    let cipher = Aes256Gcm::new(&key.into());
+    // the wrapping key is unique to the ephemeral key pair, so a zero nonce is never reused
+    cipher.decrypt(&[0u8; 12].into(), wrapped_key).ok()?.try_into().ok()
+  }
+}
+
+impl Recipient {
+  pub fn from_bytes(bytes: [u8; 32]) -> Self {
+    Self(bytes)
+  }
+
+  pub fn as_bytes(&self) -> &[u8; 32] {
+    &self.0
+  }
+
+  /// Wrap the data key for this recipient. Returns the ephemeral public key & the wrapped key.
+  pub(crate) fn wrap_key(&self, key: &[u8; 32]) -> Result<([u8; 32], Vec<u8>)> {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let ephemeral = PublicKey::from(&secret).to_bytes();
+    let shared = secret.diffie_hellman(&PublicKey::from(self.0));
+    if !shared.was_contributory() {
+      return Err(Error::InvalidValue(format!("Invalid recipient {}", self)));
+    }
+
+    let cipher = Aes256Gcm::new(&wrapping_key(shared.as_bytes(), &ephemeral, &self.0).into());
+    let wrapped_key = cipher.encrypt(&[0u8; 12].into(), key.as_slice())
+      .map_err(|err| Error::Other(format!("Failed to wrap key: {}", err)))?;
+    Ok((ephemeral, wrapped_key))
+  }
+}
+
+fn wrapping_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> [u8; 32] {
+  let salt = [ephemeral.as_slice(), recipient].concat();
+  let mut key = [0u8; 32];
+  Hkdf::<Sha256>::new(Some(&salt), shared)
+    .expand(WRAP_INFO, &mut key)
+    .expect("32 bytes is a valid HKDF-SHA256 output length");
+  key
+}
+
+fn decode_key(s: &str, expected_hrp: &str, what: &str) -> Result<[u8; 32]> {
+  let invalid = || Error::InvalidValue(format!("Invalid {} {}", what, s));
+  let (hrp, data) = bech32::decode(s).map_err(|_| invalid())?;
+  if hrp.as_str() != expected_hrp {
+    return Err(invalid());
+  }
+  data.try_into().map_err(|_| invalid())
+}
+
+fn encode_key(hrp: &str, key: &[u8; 32]) -> String {
+  bech32::encode::<Bech32>(Hrp::parse_unchecked(hrp), key)
+    .expect("32 byte keys never exceed the bech32 length limit")
+}
+
+impl FromStr for Identity {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    // bech32 is case insensitive, & identities are conventionally upper case
+    let bytes = decode_key(&s.to_lowercase(), IDENTITY_HRP, "identity")?;
+    Ok(Self(StaticSecret::from(bytes)))
+  }
+}
+
+impl fmt::Display for Identity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", encode_key(IDENTITY_HRP, self.0.as_bytes()).to_uppercase())
+  }
+}
+
+impl fmt::Debug for Identity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Identity({})", self.to_public())
+  }
+}
+
+impl FromStr for Recipient {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    decode_key(s, RECIPIENT_HRP, "recipient").map(Self)
+  }
+}
+
+impl fmt::Display for Recipient {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", encode_key(RECIPIENT_HRP, &self.0))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::format::Slot;
+  use crate::kdf::Kdf;
+  use crate::keystore::{Credential, Keystore};
+
+  #[test]
+  fn wraps_keys() {
+    let identity = Identity::generate();
+    let key = [7u8; 32];
+    let (ephemeral, wrapped_key) = identity.to_public().wrap_key(&key).unwrap();
+    assert_eq!(identity.unwrap_key(&ephemeral, &wrapped_key), Some(key));
+
+    // every wrap uses a fresh ephemeral key pair
+    let (other_ephemeral, other_wrapped_key) = identity.to_public().wrap_key(&key).unwrap();
+    assert_ne!(ephemeral, other_ephemeral);
+    assert_ne!(wrapped_key, other_wrapped_key);
+  }
+
+  #[test]
+  fn rejects_other_identities() {
+    let identity = Identity::generate();
+    let (ephemeral, mut wrapped_key) = identity.to_public().wrap_key(&[7u8; 32]).unwrap();
+    assert_eq!(Identity::generate().unwrap_key(&ephemeral, &wrapped_key), None);
+    assert_eq!(identity.unwrap_key(&[0u8; 32], &wrapped_key), None);
+    wrapped_key[0] ^= 1;
+    assert_eq!(identity.unwrap_key(&ephemeral, &wrapped_key), None);
+  }
+
+  #[test]
+  fn rotates_data_key_when_removing_recipient() {
+    let identity = Identity::generate();
+    let other = Identity::generate();
+    let operator = Credential::Password("operator".to_string());
+    let keystore = Keystore::encrypt(b"secret", "operator", Kdf::Pbkdf2Sha256 { rounds: 1000 }).unwrap()
+      .add_recipient(&operator, &identity.to_public()).unwrap()
+      .add_recipient(&operator, &other.to_public()).unwrap();
+    // the data key as unwrapped by the remaining recipient
+    let data_key = |keystore: &Keystore| keystore.slots().iter()
+      .find_map(|slot| match slot {
+        Slot::X25519 { ephemeral, wrapped_key, .. } => other.unwrap_key(ephemeral, wrapped_key),
+        _ => None,
+      })
+      .unwrap();
+
+    let removed = keystore.remove_recipient(std::slice::from_ref(&operator), &identity.to_public()).unwrap();
+    assert_ne!(data_key(&keystore), data_key(&removed));
+    assert_eq!(removed.recipients(), vec![other.to_public()]);
+    assert!(matches!(removed.unlock(&Credential::Identities(vec![identity])), Err(Error::WrongPassword)));
+    assert_eq!(removed.unlock(&Credential::Identities(vec![other])).unwrap(), b"secret");
+  }
+
+  #[test]
+  fn encodes_keys_like_age() {
+    // from the age test kit
+    let identity: Identity = "AGE-SECRET-KEY-1D8JAD8SXNFVQEFHAUNNAX4QCE3K5CUKMT7YYHNGTUSSP97YGWL4STV89UH".parse().unwrap();
+    assert_eq!(identity.to_public().to_string(), "age1uc8zlurjyjpenrslc2thyl28u7ylz6x8c2g9yphvjha6xm8ppf3slq0l25");
+    assert_eq!(identity.to_string(), "AGE-SECRET-KEY-1D8JAD8SXNFVQEFHAUNNAX4QCE3K5CUKMT7YYHNGTUSSP97YGWL4STV89UH");
+    assert_eq!(identity.to_string().to_lowercase().parse::<Identity>().unwrap().to_public(), identity.to_public());
+
+    let recipient = identity.to_public();
+    assert_eq!(recipient.to_string().parse::<Recipient>().unwrap(), recipient);
+    assert!(matches!(identity.to_string().parse::<Recipient>(), Err(Error::InvalidValue(_))));
+    assert!(matches!(recipient.to_string().parse::<Identity>(), Err(Error::InvalidValue(_))));
+    assert!(matches!("age1qqqq".parse::<Recipient>(), Err(Error::InvalidValue(_))));
+  }
+
+  #[test]
+  fn parses_identity_files() {
+    let identity = Identity::generate();
+    let contents = format!("# created: today\n# public key: {}\n\n{}\n", identity.to_public(), identity);
+    let identities = Identity::parse_file(&contents).unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].to_public(), identity.to_public());
+    assert!(matches!(Identity::parse_file("# nothing here\n"), Err(Error::InvalidValue(_))));
+    assert!(matches!(Identity::parse_file("garbage\n"), Err(Error::InvalidValue(_))));
+  }
+}
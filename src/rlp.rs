@@ -0,0 +1,201 @@
+//! Minimal Recursive Length Prefix codec as used by Ethereum transactions.
+
+/// Deepest nesting of lists accepted by [`Rlp::decode`], far beyond the 4 levels of transactions, so
+/// crafted input cannot overflow the stack.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Rlp {
+  Bytes(Vec<u8>),
+  List(Vec<Rlp>),
+}
+
+impl Rlp {
+  /// Unsigned integer as big-endian bytes without leading zeros.
+  pub fn uint(value: u128) -> Self {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    Rlp::Bytes(bytes[start..].to_vec())
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    self.encode_into(&mut out);
+    out
+  }
+
+  fn encode_into(&self, out: &mut Vec<u8>) {
+    match self {
+      Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => out.push(bytes[0]),
+      Rlp::Bytes(bytes) => {
+        write_len(out, 0x80, bytes.len());
+        out.extend_from_slice(bytes);
+      },
+      Rlp::List(items) => {
+        let mut payload = Vec::new();
+        for item in items {
+          item.encode_into(&mut payload);
+        }
+        write_len(out, 0xc0, payload.len());
+        out.extend_from_slice(&payload);
+      },
+    }
+  }
+
+  /// Decode a single item spanning all of `data`. Rejects non-canonical encodings & lists nested
+  /// deeper than 16 levels.
+  pub fn decode(data: &[u8]) -> Result<Self, String> {
+    let (item, rest) = decode_item(data, 0)?;
+    if !rest.is_empty() {
+      return Err("trailing bytes".to_string());
+    }
+    Ok(item)
+  }
+
+  pub fn as_bytes(&self) -> Result<&[u8], String> {
+    match self {
+      Rlp::Bytes(bytes) => Ok(bytes),
+      Rlp::List(_) => Err("expected bytes, found list".to_string()),
+    }
+  }
+
+  pub fn as_list(&self) -> Result<&[Rlp], String> {
+    match self {
+      Rlp::List(items) => Ok(items),
+      Rlp::Bytes(_) => Err("expected list, found bytes".to_string()),
+    }
+  }
+
+  pub fn as_uint(&self) -> Result<u128, String> {
+    let bytes = self.as_bytes()?;
+    if bytes.first() == Some(&0) {
+      return Err("integer with leading zeros".to_string());
+    }
+    if bytes.len() > 16 {
+      return Err("integer exceeds 128 bits".to_string());
+    }
+    Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u128))
+  }
+}
+
+fn write_len(out: &mut Vec<u8>, offset: u8, len: usize) {
+  if len < 56 {
+    out.push(offset + len as u8);
+  } else {
+    let bytes = len.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap();
+    out.push(offset + 55 + (bytes.len() - start) as u8);
+    out.extend_from_slice(&bytes[start..]);
+  }
+}
+
+fn decode_item(data: &[u8], depth: usize) -> Result<(Rlp, &[u8]), String> {
+  let (&prefix, rest) = data.split_first().ok_or("unexpected end of input")?;
+  match prefix {
+    0x00..=0x7f => Ok((Rlp::Bytes(vec![prefix]), rest)),
+    0x80..=0xbf => {
+      let (payload, rest) = read_payload(prefix - 0x80, rest)?;
+      if payload.len() == 1 && payload[0] < 0x80 {
+        return Err("non-canonical single byte".to_string());
+      }
+      Ok((Rlp::Bytes(payload.to_vec()), rest))
+    },
+    0xc0..=0xff => {
+      if depth >= MAX_DEPTH {
+        return Err("lists nested too deeply".to_string());
+      }
+      let (mut payload, rest) = read_payload(prefix - 0xc0, rest)?;
+      let mut items = Vec::new();
+      while !payload.is_empty() {
+        let (item, remaining) = decode_item(payload, depth + 1)?;
+        items.push(item);
+        payload = remaining;
+      }
+      Ok((Rlp::List(items), rest))
+    },
+  }
+}
+
+/// Read the payload following a prefix, given the prefix's offset from its type's base.
+fn read_payload(tag: u8, data: &[u8]) -> Result<(&[u8], &[u8]), String> {
+  let (len, data) = if tag < 56 {
+    (tag as usize, data)
+  } else {
+    let len_len = (tag - 55) as usize;
+    if data.len() < len_len {
+      return Err("unexpected end of input".to_string());
+    }
+    let (len_bytes, data) = data.split_at(len_len);
+    if len_bytes[0] == 0 || len_len > std::mem::size_of::<usize>() {
+      return Err("non-canonical length".to_string());
+    }
+    let len = len_bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+    if len < 56 {
+      return Err("non-canonical length".to_string());
+    }
+    (len, data)
+  };
+  if data.len() < len {
+    return Err("unexpected end of input".to_string());
+  }
+  Ok(data.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn bytes(value: &str) -> Rlp {
+    Rlp::Bytes(value.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn encodes_known_vectors() {
+    let cases = [
+      (bytes("dog"), "83646f67"),
+      (Rlp::List(vec![bytes("cat"), bytes("dog")]), "c88363617483646f67"),
+      (bytes(""), "80"),
+      (Rlp::List(vec![]), "c0"),
+      (Rlp::uint(0), "80"),
+      (Rlp::uint(15), "0f"),
+      (Rlp::uint(1024), "820400"),
+      (
+        bytes("Lorem ipsum dolor sit amet, consectetur adipisicing elit"),
+        "b8384c6f72656d20697073756d20646f6c6f722073697420616d65742c20636f6e7365637465747572206164697069736963696e6720656c6974",
+      ),
+      (
+        Rlp::List(vec![
+          Rlp::List(vec![]),
+          Rlp::List(vec![Rlp::List(vec![])]),
+          Rlp::List(vec![Rlp::List(vec![]), Rlp::List(vec![Rlp::List(vec![])])]),
+        ]),
+        "c7c0c1c0c3c0c1c0",
+      ),
+    ];
+    for (item, encoded) in cases {
+      assert_eq!(hex::encode(item.encode()), encoded);
+      assert_eq!(Rlp::decode(&hex::decode(encoded).unwrap()).unwrap(), item);
+    }
+  }
+
+  #[test]
+  fn rejects_non_canonical_encodings() {
+    for encoded in ["8100", "b800", "b90001", "c1", "83646f", "83646f6700"] {
+      assert!(Rlp::decode(&hex::decode(encoded).unwrap()).is_err(), "{}", encoded);
+    }
+    assert!(Rlp::Bytes(vec![0, 1]).as_uint().is_err());
+  }
+
+  #[test]
+  fn limits_nesting_depth() {
+    let nested = |depth: usize| {
+      let mut item = Rlp::List(vec![]);
+      for _ in 0..depth {
+        item = Rlp::List(vec![item]);
+      }
+      item.encode()
+    };
+    assert!(Rlp::decode(&nested(MAX_DEPTH - 1)).is_ok());
+    assert_eq!(Rlp::decode(&nested(MAX_DEPTH)), Err("lists nested too deeply".to_string()));
+  }
+}
@@ -0,0 +1,222 @@
+//! Remote signer speaking Ethereum JSON-RPC over HTTP, as supported by Foundry, Hardhat & co.
+//! Implements `eth_accounts`, `eth_sign`, `personal_sign`, `eth_signTransaction` &
+//! `eth_signTypedData_v4`.
+use std::io::Read;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::eip712::TypedData;
+use crate::error::{Error, Result};
+use crate::eth::{self, to_checksum_address};
+use crate::eth_tx::Transaction;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SIGNING_FAILED: i64 = -32000;
+
+/// Largest request body accepted, far above any transaction or typed data worth signing.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Signer holding the private keys of its accounts.
+#[derive(Default)]
+pub struct Signer {
+  accounts: Vec<([u8; 20], Vec<u8>)>,
+  silent: bool,
+}
+
+struct RpcError(i64, String);
+
+impl Signer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Do not log signing requests to stderr.
+  pub fn with_silent(mut self, silent: bool) -> Self {
+    self.silent = silent;
+    self
+  }
+
+  /// Add the account of the given secp256k1 private key & return its address.
+  pub fn add_key(&mut self, privkey: Vec<u8>) -> Result<[u8; 20]> {
+    let address = eth::address(&privkey)?;
+    self.accounts.push((address, privkey));
+    Ok(address)
+  }
+
+  pub fn accounts(&self) -> Vec<[u8; 20]> {
+    self.accounts.iter().map(|(address, _)| *address).collect()
+  }
+
+  /// Serve JSON-RPC over HTTP on the given address until the process is terminated.
+  ///
+  /// Only `application/json` requests without an `Origin` are accepted, so websites visited in a
+  /// browser cannot make use of the signer.
+  pub fn serve(&self, addr: &str) -> Result<()> {
+    let server = Server::http(addr)
+      .map_err(|err| Error::Other(format!("Could not listen on {}: {}", addr, err)))?;
+
+    for mut request in server.incoming_requests() {
+      let header = |name: &'static str| request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_string());
+
+      let status = if *request.method() != Method::Post {
+        Some(405)
+      } else if header("Origin").is_some() {
+        Some(403)
+      } else if !header("Content-Type").is_some_and(|value| value.starts_with("application/json")) {
+        Some(415)
+      } else if request.body_length().is_some_and(|length| length > MAX_BODY) {
+        Some(413)
+      } else {
+        None
+      };
+      if let Some(status) = status {
+        let _ = request.respond(Response::empty(status));
+        continue;
+      }
+
+      // chunked bodies have no length upfront, so read at most one byte past the limit to detect excess
+      let mut body = String::new();
+      let response = match request.as_reader().take(MAX_BODY as u64 + 1).read_to_string(&mut body) {
+        Ok(read) if read > MAX_BODY => {
+          let _ = request.respond(Response::empty(413));
+          continue;
+        },
+        Ok(_) => self.handle(&body),
+        Err(_) => error_response(Value::Null, RpcError(PARSE_ERROR, "Could not read request".to_string())),
+      };
+      let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+      let _ = request.respond(Response::from_string(response.to_string()).with_header(content_type));
+    }
+    Ok(())
+  }
+
+  /// Handle a JSON-RPC request or batch of requests.
+  pub fn handle(&self, body: &str) -> Value {
+    match serde_json::from_str::<Value>(body) {
+      Ok(Value::Array(batch)) if batch.is_empty() => error_response(Value::Null, RpcError(INVALID_REQUEST, "Empty batch".to_string())),
+      Ok(Value::Array(batch)) => batch.iter().map(|request| self.handle_one(request)).collect(),
+      Ok(request) => self.handle_one(&request),
+      Err(err) => error_response(Value::Null, RpcError(PARSE_ERROR, err.to_string())),
+    }
+  }
+
+  fn handle_one(&self, request: &Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+      return error_response(id, RpcError(INVALID_REQUEST, "Missing method".to_string()));
+    };
+    let params = request.get("params").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+
+    match self.call(method, params) {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Err(err) => error_response(id, err),
+    }
+  }
+
+  fn call(&self, method: &str, params: &[Value]) -> std::result::Result<Value, RpcError> {
+    let param = |i: usize| params.get(i).ok_or_else(|| invalid_params(format!("Missing parameter {}", i)));
+    let (signer, result) = match method {
+      "eth_accounts" => {
+        let accounts = self.accounts.iter().map(|(address, _)| to_checksum_address(address));
+        return Ok(accounts.collect());
+      },
+      "eth_sign" | "personal_sign" => {
+        // eth_sign takes the address first, personal_sign the message
+        let (address, message) = if method == "eth_sign" { (param(0)?, param(1)?) } else { (param(1)?, param(0)?) };
+        let (address, privkey) = self.account(address)?;
+        let message = unhex(message)?;
+        (address, eth::sign_message(privkey, &message).map(|sig| hex_value(&sig.to_bytes())))
+      },
+      "eth_signTransaction" => {
+        let tx = param(0)?;
+        let (address, privkey) = self.account(tx.get("from").unwrap_or(&Value::Null))?;
+        let tx = Transaction::from_json(&tx.to_string()).map_err(|err| invalid_params(err.to_string()))?;
+        (address, tx.sign(privkey).map(|raw| hex_value(&raw)))
+      },
+      "eth_signTypedData_v4" => {
+        let (address, privkey) = self.account(param(0)?)?;
+        let data = match param(1)? {
+          Value::String(json) => json.clone(),
+          data => data.to_string(),
+        };
+        let hash = TypedData::from_json(&data)
+          .and_then(|data| data.signing_hash())
+          .map_err(|err| invalid_params(err.to_string()))?;
+        (address, eth::sign_hash(privkey, &hash).map(|sig| hex_value(&sig.to_bytes())))
+      },
+      _ => return Err(RpcError(METHOD_NOT_FOUND, format!("Method {} not found", method))),
+    };
+
+    if !self.silent {
+      eprintln!("{} by {}", method, to_checksum_address(&signer));
+    }
+    result.map_err(|err| RpcError(SIGNING_FAILED, err.to_string()))
+  }
+
+  fn account(&self, address: &Value) -> std::result::Result<([u8; 20], &[u8]), RpcError> {
+    let address = unhex(address)?;
+    self.accounts.iter()
+      .find(|(candidate, _)| candidate[..] == address[..])
+      .map(|(address, privkey)| (*address, privkey.as_slice()))
+      .ok_or_else(|| invalid_params(format!("Unknown account 0x{}", hex::encode(address))))
+  }
+}
+
+fn unhex(value: &Value) -> std::result::Result<Vec<u8>, RpcError> {
+  value.as_str()
+    .and_then(|value| hex::decode(value.trim_start_matches("0x")).ok())
+    .ok_or_else(|| invalid_params(format!("Expected hex string, found {}", value)))
+}
+
+fn hex_value(bytes: &[u8]) -> Value {
+  Value::String(format!("0x{}", hex::encode(bytes)))
+}
+
+fn invalid_params(message: String) -> RpcError {
+  RpcError(INVALID_PARAMS, message)
+}
+
+fn error_response(id: Value, RpcError(code, message): RpcError) -> Value {
+  json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn signer() -> Signer {
+    let mut signer = Signer::new().with_silent(true);
+    signer.add_key(hex::decode("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318").unwrap()).unwrap();
+    signer
+  }
+
+  #[test]
+  fn handles_batches() {
+    let response = signer().handle(r#"[{"id":1,"method":"eth_accounts"},{"id":2,"method":"eth_foo"}]"#);
+    assert_eq!(response[0]["result"], json!(["0x2c7536E3605D9C16a7a3D7b1898e529396a65c23"]));
+    assert_eq!(response[1]["error"]["code"], METHOD_NOT_FOUND);
+  }
+
+  #[test]
+  fn rejects_empty_batches() {
+    let response = signer().handle("[]");
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+    assert_eq!(response["id"], Value::Null);
+  }
+
+  #[test]
+  fn refuses_transactions_without_chain_id() {
+    let request = json!({ "id": 1, "method": "eth_signTransaction", "params": [{
+      "from": "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23",
+      "nonce": "0x0", "gasPrice": "0x1", "gas": "0x5208", "to": "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23", "value": "0x0",
+    }]});
+    let response = signer().handle(&request.to_string());
+    assert!(response.get("result").is_none());
+  }
+}
@@ -0,0 +1,229 @@
+//! Templates with placeholders for stored secrets, e.g. `KEY={{ kayring "deployer" }}` or
+//! `ADDRESS={{ kayring "deployer" | address "cosmos:osmo" }}`. Any other `{{ ... }}` is left as is.
+use crate::address::Chain;
+use crate::error::{Error, Result};
+
+/// Representation of a secret in the rendered template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+  /// The secret in the representation it was stored with. The default.
+  Value,
+  /// `0x` prefixed hex string.
+  Hex,
+  /// Hex string without prefix.
+  NoHex,
+  /// Address of the key on the given chain, Ethereum if omitted.
+  Address(Chain),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+  Text(String),
+  Secret { name: String, filter: Filter },
+}
+
+/// A parsed template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+  parts: Vec<Part>,
+}
+
+impl Template {
+  pub fn parse(source: &str) -> Result<Self> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+      let line = source[..source.len() - rest.len() + start].matches('\n').count() + 1;
+      let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+        if placeholder_args(&rest[start + 2..]).is_some() {
+          return Err(Error::InvalidValue(format!("Invalid template at line {}: unterminated placeholder", line)));
+        }
+        break;
+      };
+
+      match placeholder_args(&rest[start + 2..end]) {
+        Some(args) => {
+          let (name, filter) = parse_placeholder(args)
+            .map_err(|err| Error::InvalidValue(format!("Invalid template at line {}: {}", line, err)))?;
+          text.push_str(&rest[..start]);
+          parts.push(Part::Text(std::mem::take(&mut text)));
+          parts.push(Part::Secret { name, filter });
+        },
+        None => text.push_str(&rest[..end + 2]),
+      }
+      rest = &rest[end + 2..];
+    }
+    text.push_str(rest);
+    parts.push(Part::Text(text));
+    parts.retain(|part| *part != Part::Text(String::new()));
+
+    Ok(Self { parts })
+  }
+
+  /// Distinct names of the secrets referenced by the template, in order of first appearance.
+  pub fn names(&self) -> Vec<&str> {
+    let mut names = vec![];
+    for part in &self.parts {
+      if let Part::Secret { name, .. } = part {
+        if !names.contains(&name.as_str()) {
+          names.push(name.as_str());
+        }
+      }
+    }
+    names
+  }
+
+  /// Render the template, replacing each placeholder with the result of `resolve`.
+  pub fn render(&self, mut resolve: impl FnMut(&str, &Filter) -> Result<String>) -> Result<String> {
+    let mut output = String::new();
+    for part in &self.parts {
+      match part {
+        Part::Text(text) => output.push_str(text),
+        Part::Secret { name, filter } => output.push_str(&resolve(name, filter)?),
+      }
+    }
+    Ok(output)
+  }
+}
+
+/// The arguments of a `{{ kayring ... }}` placeholder given its contents, or `None` for other `{{ ... }}`.
+fn placeholder_args(inner: &str) -> Option<&str> {
+  inner.trim_start().strip_prefix("kayring").filter(|args| args.starts_with(char::is_whitespace))
+}
+
+/// Parse the arguments of a placeholder: `"<name>" [| <filter> ["<argument>"]]`.
+fn parse_placeholder(args: &str) -> std::result::Result<(String, Filter), String> {
+  let tokens = tokenize(args)?;
+  let (name, filter) = match tokens.as_slice() {
+    [Token::Quoted(name)] => (name, None),
+    [Token::Quoted(name), Token::Pipe, filter @ ..] => (name, Some(filter)),
+    _ => return Err(format!("expected kayring \"<name>\" [| <filter>], found kayring {}", args.trim())),
+  };
+
+  let filter = match filter {
+    None => Filter::Value,
+    Some([Token::Word("hex")]) => Filter::Hex,
+    Some([Token::Word("nohex")]) => Filter::NoHex,
+    Some([Token::Word("address")]) => Filter::Address(Chain::Eth),
+    Some([Token::Word("address"), Token::Quoted(chain)]) => {
+      Filter::Address(chain.parse().map_err(|_| format!("unknown chain {}", chain))?)
+    },
+    _ => return Err(format!("unknown filter {}. Expected hex, nohex or address [\"<chain>\"]", args.split_once('|').unwrap().1.trim())),
+  };
+  Ok((name.to_string(), filter))
+}
+
+#[derive(Debug)]
+enum Token<'a> {
+  Quoted(&'a str),
+  Word(&'a str),
+  Pipe,
+}
+
+fn tokenize(mut input: &str) -> std::result::Result<Vec<Token<'_>>, String> {
+  let mut tokens = vec![];
+  loop {
+    input = input.trim_start();
+    if input.is_empty() {
+      return Ok(tokens);
+    }
+
+    if let Some(rest) = input.strip_prefix('|') {
+      tokens.push(Token::Pipe);
+      input = rest;
+    } else if let Some(rest) = input.strip_prefix('"') {
+      let end = rest.find('"').ok_or_else(|| "unterminated string".to_string())?;
+      tokens.push(Token::Quoted(&rest[..end]));
+      input = &rest[end + 1..];
+    } else {
+      let end = input.find(|c: char| c.is_whitespace() || c == '|' || c == '"').unwrap_or(input.len());
+      tokens.push(Token::Word(&input[..end]));
+      input = &input[end..];
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PRIVKEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+  fn render(source: &str) -> Result<String> {
+    Template::parse(source)?.render(|name, filter| {
+      if name != "deployer" {
+        return Err(Error::NotFound(name.to_string()));
+      }
+      let privkey = hex::decode(PRIVKEY).unwrap();
+      match filter {
+        Filter::Value => Ok(format!("value:{}", name)),
+        Filter::Hex => Ok(format!("0x{}", PRIVKEY)),
+        Filter::NoHex => Ok(PRIVKEY.to_string()),
+        Filter::Address(chain) => Ok(chain.account(&privkey)?.address),
+      }
+    })
+  }
+
+  fn secret(name: &str, filter: Filter) -> Part {
+    Part::Secret { name: name.to_string(), filter }
+  }
+
+  #[test]
+  fn parses_placeholders() {
+    let template = Template::parse("A={{ kayring \"a\" }}\nB={{kayring \"b\"|hex}} {{ kayring \"a\" | address \"solana\" }}").unwrap();
+    assert_eq!(template.parts, vec![
+      Part::Text("A=".to_string()),
+      secret("a", Filter::Value),
+      Part::Text("\nB=".to_string()),
+      secret("b", Filter::Hex),
+      Part::Text(" ".to_string()),
+      secret("a", Filter::Address(Chain::Solana)),
+    ]);
+    assert_eq!(template.names(), vec!["a", "b"]);
+  }
+
+  #[test]
+  fn renders_filters() {
+    assert_eq!(render("KEY={{ kayring \"deployer\" }}\n").unwrap(), "KEY=value:deployer\n");
+    assert_eq!(render("{{ kayring \"deployer\" | hex }}").unwrap(), format!("0x{}", PRIVKEY));
+    assert_eq!(render("{{ kayring \"deployer\" | nohex }}").unwrap(), PRIVKEY);
+    assert_eq!(render("{{ kayring \"deployer\" | address }}").unwrap(), "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23");
+    assert_eq!(
+      render("{{ kayring \"deployer\" | address \"cosmos:osmo\" }}").unwrap(),
+      Chain::Cosmos("osmo".to_string()).account(&hex::decode(PRIVKEY).unwrap()).unwrap().address,
+    );
+  }
+
+  #[test]
+  fn leaves_other_braces() {
+    let source = "{{ .Values.foo }} {{kayringx}} {{ kayring}} }} {{";
+    assert_eq!(render(source).unwrap(), source);
+    assert_eq!(Template::parse(source).unwrap().names(), Vec::<&str>::new());
+  }
+
+  #[test]
+  fn fails_on_missing_keys() {
+    assert!(matches!(render("{{ kayring \"deployer\" }} {{ kayring \"missing\" }}"), Err(Error::NotFound(name)) if name == "missing"));
+  }
+
+  #[test]
+  fn rejects_invalid_placeholders() {
+    for source in [
+      "{{ kayring }}",
+      "{{ kayring deployer }}",
+      "{{ kayring \"deployer }}",
+      "{{ kayring \"deployer\" | base64 }}",
+      "{{ kayring \"deployer\" | address \"foo\" }}",
+      "{{ kayring \"deployer\" | hex \"eth\" }}",
+      "{{ kayring \"deployer\" \"other\" }}",
+      "{{ kayring \"deployer\"",
+      "A=1\nB={{ kayring \"deployer\" | hex",
+    ] {
+      assert!(matches!(Template::parse(source), Err(Error::InvalidValue(_))), "{}", source);
+    }
+    let Err(Error::InvalidValue(message)) = Template::parse("A=1\nB={{ kayring \"deployer\"") else { unreachable!() };
+    assert!(message.contains("line 2"), "{}", message);
+  }
+}